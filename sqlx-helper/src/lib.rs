@@ -11,6 +11,36 @@ use syn::visit_mut::VisitMut;
 struct ArgsFromAttrs {
     #[darling(default)]
     table_name: Option<String>,
+    /// The column that identifies a row, needed to load rows by id.
+    #[darling(default)]
+    primary_key: Option<String>,
+    /// What to do if a row with the same key already exists, `replace`, `ignore` or `update`.
+    #[darling(default)]
+    on_conflict: Option<String>,
+    /// The column that identifies a row, needed for `update`, the primary key by default.
+    #[darling(default)]
+    conflict_key: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum OnConflict {
+    Replace,
+    Ignore,
+    Update,
+}
+
+impl OnConflict {
+    fn parse(s: &str) -> OnConflict {
+        match s {
+            "replace" => OnConflict::Replace,
+            "ignore" => OnConflict::Ignore,
+            "update" => OnConflict::Update,
+            _ => panic!(
+                "unknown on_conflict {:?}, expected replace, ignore or update",
+                s
+            ),
+        }
+    }
 }
 
 #[proc_macro_attribute]
@@ -21,11 +51,15 @@ pub fn insert(_attr: ExternTokenStream, item: ExternTokenStream) -> ExternTokenS
 #[proc_macro_attribute]
 pub fn insertable(attr: ExternTokenStream, item: ExternTokenStream) -> ExternTokenStream {
     let attr_args = syn::parse_macro_input!(attr as syn::AttributeArgs);
-    let mut item = syn::parse_macro_input!(item as syn::ItemStruct);
+    let item = syn::parse_macro_input!(item as syn::ItemStruct);
+
+    expand(&attr_args, item).into()
+}
 
-    let args = match ArgsFromAttrs::from_list(&attr_args) {
+fn expand(attr_args: &syn::AttributeArgs, mut item: syn::ItemStruct) -> proc_macro2::TokenStream {
+    let args = match ArgsFromAttrs::from_list(attr_args) {
         Ok(v) => v,
-        Err(e) => return e.write_errors().into(),
+        Err(e) => return e.write_errors(),
     };
 
     let struct_name = item.ident.clone();
@@ -37,6 +71,20 @@ pub fn insertable(attr: ExternTokenStream, item: ExternTokenStream) -> ExternTok
         proc_macro2::Span::call_site(),
     );
 
+    let on_conflict = args.on_conflict.as_deref().map(OnConflict::parse);
+    let primary_key = args
+        .primary_key
+        .map(|key| syn::Ident::new(&key, proc_macro2::Span::call_site()));
+    let conflict_key = args
+        .conflict_key
+        .map(|key| syn::Ident::new(&key, proc_macro2::Span::call_site()))
+        .or_else(|| primary_key.clone());
+
+    assert!(
+        on_conflict != Some(OnConflict::Update) || conflict_key.is_some(),
+        "on_conflict = \"update\" needs a conflict_key"
+    );
+
     let mut remover = FieldAttrRemover::new();
     remover.visit_item_struct_mut(&mut item);
 
@@ -44,20 +92,104 @@ pub fn insertable(attr: ExternTokenStream, item: ExternTokenStream) -> ExternTok
 
     let mut field_names = vec![];
     let mut field_access = vec![];
+    let mut key_access = None;
     let mut embedded_field_access = vec![];
     let mut embedded_field_insert_stmt = vec![];
+    let mut stale_children = vec![];
+    let mut primary_key_type = None;
+    let mut load_fields = vec![];
+    let mut load_children = vec![];
+    let mut column_defs = vec![];
+    let mut indexes = vec![];
+    let mut child_tables = vec![];
+
+    for (name, ty, args) in my_fields {
+        if Some(&name) == primary_key.as_ref() {
+            primary_key_type = Some(ty.clone());
+        }
 
-    for (name, args) in my_fields {
         if args.skip {
+            load_fields.push(quote! {
+                #name: ::std::default::Default::default()
+            });
+
             continue;
         }
 
         match args.embed_with {
             Some(module) => {
+                assert!(
+                    on_conflict != Some(OnConflict::Ignore),
+                    "on_conflict = \"ignore\" would insert the embedded {} again for rows that exist",
+                    name
+                );
+
+                // the children of a row that already exists are replaced by the new ones
+                if on_conflict.is_some() {
+                    let foreign_key = args.foreign_key.clone().unwrap_or_else(|| {
+                        panic!(
+                            "embedded {} needs a foreign_key to remove the stale rows on conflicts",
+                            name
+                        )
+                    });
+
+                    stale_children
+                        .push(format!("DELETE FROM {} WHERE {} = $1", module, foreign_key));
+                }
+
+                let loaded_module = syn::Ident::new(&module, proc_macro2::Span::call_site());
+                let foreign_key = args.foreign_key.clone().unwrap_or_else(|| {
+                    panic!("embedded {} needs a foreign_key to be loaded", name)
+                });
+                let parent_key = primary_key
+                    .as_ref()
+                    .expect("loading embedded rows needs a primary_key");
+
+                load_fields.push(quote! {
+                    #name: ::std::default::Default::default()
+                });
+
+                child_tables.push(quote! {
+                    #loaded_module::create_table_sql_referencing(&[(#foreign_key, references)])
+                });
+
+                let convert = match &args.load_with {
+                    Some(converter) => {
+                        let converter = syn::Ident::new(converter, proc_macro2::Span::call_site());
+
+                        quote! {
+                            .map(#converter)
+                            .collect::<::std::result::Result<_, _>>()
+                            .map_err(|e| sqlx::Error::Decode(e.into()))?
+                        }
+                    }
+                    None => quote! { .collect() },
+                };
+
+                load_children.push(quote! {
+                    let keys = values
+                        .iter()
+                        .map(|value| value.#parent_key.clone())
+                        .collect::<Vec<_>>();
+                    let mut children = ::std::collections::HashMap::<_, Vec<_>>::new();
+
+                    for (key, child) in #loaded_module::load_embedded(&mut *conn, #foreign_key, &keys).await? {
+                        children.entry(key).or_default().push(child);
+                    }
+
+                    for value in values.iter_mut() {
+                        value.#name = children
+                            .remove(&value.#parent_key)
+                            .unwrap_or_default()
+                            .into_iter()
+                            #convert;
+                    }
+                });
+
                 let module = syn::Ident::new(&module, proc_macro2::Span::call_site());
 
                 embedded_field_access.push(quote! {
-                    &$value.#name
+                    &value.#name
                 });
 
                 match args.embed_translator {
@@ -66,7 +198,7 @@ pub fn insertable(attr: ExternTokenStream, item: ExternTokenStream) -> ExternTok
                             syn::Ident::new(&translator, proc_macro2::Span::call_site());
 
                         embedded_field_insert_stmt.push(quote! {
-                            $crate::#module::insert!($crate::#translator(&$value, v), |$query| $execute);
+                            $crate::#module::insert!($crate::#translator(value, v), |$query| $execute);
                         })
                     }
                     None => embedded_field_insert_stmt.push(quote! {
@@ -81,18 +213,75 @@ pub fn insertable(attr: ExternTokenStream, item: ExternTokenStream) -> ExternTok
                 );
                 field_names.push(name.clone());
 
-                field_access.push(match args.with {
+                let column = name.to_string();
+
+                let sql_type = match (&args.sql_type, &args.with) {
+                    (Some(sql_type), _) => quote! { #sql_type.to_string() },
+                    (None, Some(converter)) => {
+                        let converter = syn::Ident::new(converter, proc_macro2::Span::call_site());
+
+                        quote! { sql_type_of(#converter) }
+                    }
+                    (None, None) => quote! {
+                        <#ty as sqlx::Type<sqlx::Sqlite>>::type_info().to_string()
+                    },
+                };
+                let constraint = if Some(&name) == primary_key.as_ref() {
+                    " PRIMARY KEY NOT NULL"
+                } else if is_option(&ty) {
+                    ""
+                } else {
+                    " NOT NULL"
+                };
+
+                column_defs.push(quote! {
+                    (#column, #sql_type, #constraint)
+                });
+
+                if args.index {
+                    indexes.push(column.clone());
+                }
+
+                load_fields.push(match &args.load_with {
+                    Some(converter) => {
+                        let converter = syn::Ident::new(converter, proc_macro2::Span::call_site());
+
+                        quote! {
+                            #name: #converter(sqlx::Row::try_get(row, #column)?)
+                                .map_err(|e| sqlx::Error::Decode(e.into()))?
+                        }
+                    }
+                    None => {
+                        assert!(
+                            args.with.is_none(),
+                            "{} is written with a converter, it needs a load_with to be read back",
+                            name
+                        );
+
+                        quote! {
+                            #name: sqlx::Row::try_get(row, #column)?
+                        }
+                    }
+                });
+
+                let access = match args.with {
                     Some(accessor) => {
                         let accessor = syn::Ident::new(&accessor, proc_macro2::Span::call_site());
 
                         quote! {
-                            $crate::#accessor($value.#name)
+                            $crate::#accessor(&value.#name)
                         }
                     }
                     None => quote! {
-                        $value.#name
+                        value.#name
                     },
-                });
+                };
+
+                if Some(&name) == conflict_key.as_ref() {
+                    key_access = Some(access.clone());
+                }
+
+                field_access.push(access);
             }
         }
     }
@@ -101,7 +290,7 @@ pub fn insertable(attr: ExternTokenStream, item: ExternTokenStream) -> ExternTok
     insert_statement.push_str(&table_name.to_string());
     insert_statement.push_str(" (");
 
-    if field_names.len() > 0 {
+    if !field_names.is_empty() {
         insert_statement.push_str(&field_names[0].to_string());
 
         for field in &field_names[1..] {
@@ -112,7 +301,7 @@ pub fn insertable(attr: ExternTokenStream, item: ExternTokenStream) -> ExternTok
 
     insert_statement.push_str(") VALUES (");
 
-    if field_names.len() > 0 {
+    if !field_names.is_empty() {
         insert_statement.push_str("$1");
 
         for i in 2..(field_names.len() + 1) {
@@ -121,7 +310,182 @@ pub fn insertable(attr: ExternTokenStream, item: ExternTokenStream) -> ExternTok
         }
     }
 
-    insert_statement.push_str(")");
+    insert_statement.push(')');
+
+    match on_conflict {
+        // sqlite deletes the old row and inserts the new one
+        Some(OnConflict::Replace) => {
+            insert_statement = insert_statement.replacen("INSERT", "INSERT OR REPLACE", 1)
+        }
+        Some(OnConflict::Ignore) => {
+            insert_statement.push_str(" ON CONFLICT");
+
+            if let Some(key) = &conflict_key {
+                insert_statement.push_str(&format!("({})", key));
+            }
+
+            insert_statement.push_str(" DO NOTHING");
+        }
+        Some(OnConflict::Update) => {
+            let key = conflict_key.as_ref().unwrap();
+
+            let updates = field_names
+                .iter()
+                .filter(|name| *name != key)
+                .map(|name| format!("{} = excluded.{}", name, name))
+                .collect::<Vec<_>>();
+
+            if updates.is_empty() {
+                insert_statement.push_str(&format!(" ON CONFLICT({}) DO NOTHING", key));
+            } else {
+                insert_statement.push_str(&format!(
+                    " ON CONFLICT({}) DO UPDATE SET {}",
+                    key,
+                    updates.join(", ")
+                ));
+            }
+        }
+        None => {}
+    }
+
+    let delete_stale_children = stale_children
+        .iter()
+        .map(|statement| {
+            let key_access = key_access
+                .as_ref()
+                .expect("removing the stale embedded rows needs a conflict_key that is inserted");
+
+            quote! {
+                let $query = sqlx::query!(#statement, #key_access);
+                $execute;
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let table = table_name.to_string();
+
+    // the embedded tables refer to this one
+    let references = match &primary_key {
+        Some(key) => format!("REFERENCES {} ({}) ON DELETE CASCADE", table, key),
+        None => String::new(),
+    };
+    let columns = field_names
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let loaders_by_key = match (&primary_key, &primary_key_type) {
+        (Some(key), Some(key_type)) => {
+            let key = key.to_string();
+
+            quote! {
+                /// The row with the primary key `id`.
+                ///
+                /// Skipped fields come back as their default and fields written with a
+                /// converter as what their `load_with` makes of the column, so a loaded row
+                /// only has what the table stores.
+                pub async fn load_by_id(
+                    conn: &mut sqlx::SqliteConnection,
+                    id: #key_type,
+                ) -> ::std::result::Result<Option<#struct_name>, sqlx::Error> {
+                    Ok(load_by_ids(conn, &[id]).await?.pop())
+                }
+
+                /// The rows with the primary keys `ids`, ordered by the primary key, ids
+                /// without a row are skipped. Like `load_by_id`, only has what the table
+                /// stores.
+                pub async fn load_by_ids(
+                    conn: &mut sqlx::SqliteConnection,
+                    ids: &[#key_type],
+                ) -> ::std::result::Result<Vec<#struct_name>, sqlx::Error> {
+                    let mut values = vec![];
+
+                    for chunk in ids.chunks(BATCH) {
+                        let placeholders = (1..=chunk.len())
+                            .map(|i| format!("${}", i))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        let statement = format!(
+                            "SELECT {} FROM {} WHERE {} IN ({}) ORDER BY {}",
+                            COLUMNS, TABLE, #key, placeholders, #key
+                        );
+
+                        let mut query = sqlx::query(&statement);
+
+                        for id in chunk {
+                            query = query.bind(id.clone());
+                        }
+
+                        let rows = query.fetch_all(&mut *conn).await?;
+                        let mut chunk_values = rows
+                            .iter()
+                            .map(from_row)
+                            .collect::<::std::result::Result<Vec<_>, _>>()?;
+
+                        load_children(conn, &mut chunk_values).await?;
+                        values.append(&mut chunk_values);
+                    }
+
+                    Ok(values)
+                }
+
+                /// All rows, ordered by the primary key. Like `load_by_id`, only has what
+                /// the table stores.
+                ///
+                /// The keys are read first and the rows in batches, as the connection can't
+                /// stream rows and load their embedded rows at the same time.
+                pub fn load_all(
+                    conn: &mut sqlx::SqliteConnection,
+                ) -> impl futures_util::stream::Stream<Item = ::std::result::Result<#struct_name, sqlx::Error>> + '_ {
+                    let state: (_, Option<::std::vec::IntoIter<#key_type>>, ::std::collections::VecDeque<#struct_name>) =
+                        (conn, None, ::std::collections::VecDeque::new());
+
+                    futures_util::stream::unfold(state, |(conn, keys, mut loaded)| async move {
+                        let mut keys = match keys {
+                            Some(keys) => keys,
+                            None => {
+                                let statement = format!("SELECT {} FROM {} ORDER BY {}", #key, TABLE, #key);
+
+                                let keys = sqlx::query(&statement)
+                                    .fetch_all(&mut *conn)
+                                    .await
+                                    .and_then(|rows| {
+                                        rows.iter()
+                                            .map(|row| sqlx::Row::try_get(row, 0usize))
+                                            .collect::<::std::result::Result<Vec<_>, _>>()
+                                    });
+
+                                match keys {
+                                    Ok(keys) => keys.into_iter(),
+                                    Err(e) => return Some((Err(e), (conn, Some(Vec::new().into_iter()), loaded))),
+                                }
+                            }
+                        };
+
+                        loop {
+                            if let Some(value) = loaded.pop_front() {
+                                return Some((Ok(value), (conn, Some(keys), loaded)));
+                            }
+
+                            let batch = keys.by_ref().take(BATCH).collect::<Vec<_>>();
+
+                            if batch.is_empty() {
+                                return None;
+                            }
+
+                            // rows deleted since we read the keys are skipped
+                            match load_by_ids(&mut *conn, &batch).await {
+                                Ok(values) => loaded.extend(values),
+                                Err(e) => return Some((Err(e), (conn, Some(keys), loaded))),
+                            }
+                        }
+                    })
+                }
+            }
+        }
+        _ => quote! {},
+    };
 
     let insert_name = syn::Ident::new(
         &("_".to_owned() + &table_name.to_string() + "_insert"),
@@ -134,41 +498,199 @@ pub fn insertable(attr: ExternTokenStream, item: ExternTokenStream) -> ExternTok
         pub mod #table_name {
             use super::*;
 
+            // the row goes first, so the embedded rows can refer to it, the embedded rows of
+            // a row that existed are replaced by the new ones
             #[macro_export]
             macro_rules! #insert_name {
                 ($value:expr, |$query:ident| $execute:block) => {
-                    #(for v in #embedded_field_access {
-                        #embedded_field_insert_stmt
-                    })*
+                    let value = &$value;
 
                     let $query = sqlx::query!(#insert_statement, #(#field_access),*);
                     $execute;
+
+                    #(#delete_stale_children)*
+
+                    #(for v in #embedded_field_access {
+                        #embedded_field_insert_stmt
+                    })*
                 }
             }
 
             // cool hack, see https://github.com/SergioBenitez/Rocket/issues/19#issuecomment-453822603
             pub use #insert_name as insert;
+
+            const TABLE: &str = #table;
+            const COLUMNS: &str = #columns;
+            // rows are loaded with one parameter per key, sqlite allows at most 999 parameters
+            #[allow(dead_code)]
+            const BATCH: usize = 500;
+
+            fn from_row(row: &sqlx::sqlite::SqliteRow) -> ::std::result::Result<#struct_name, sqlx::Error> {
+                Ok(#struct_name {
+                    #(#load_fields),*
+                })
+            }
+
+            // one query per embedded table for all of `values`
+            #[allow(unused_variables, dead_code)]
+            async fn load_children(
+                conn: &mut sqlx::SqliteConnection,
+                values: &mut [#struct_name],
+            ) -> ::std::result::Result<(), sqlx::Error> {
+                #(#load_children)*
+
+                Ok(())
+            }
+
+            // the rows where `column` is one of `keys`, each with its `column`, in the order they
+            // were inserted, for the tables that embed this one. `column` is put into the sql as
+            // it is, so it only ever gets the `foreign_key` of an embedding field
+            #[allow(dead_code)]
+            pub(crate) async fn load_embedded<K>(
+                conn: &mut sqlx::SqliteConnection,
+                column: &str,
+                keys: &[K],
+            ) -> ::std::result::Result<Vec<(K, #struct_name)>, sqlx::Error>
+            where
+                K: for<'q> sqlx::Encode<'q, sqlx::Sqlite>
+                    + for<'r> sqlx::Decode<'r, sqlx::Sqlite>
+                    + sqlx::Type<sqlx::Sqlite>
+                    + Clone
+                    + Send,
+            {
+                let mut keyed = vec![];
+
+                for chunk in keys.chunks(BATCH) {
+                    let placeholders = (1..=chunk.len())
+                        .map(|i| format!("${}", i))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let statement = format!(
+                        "SELECT {}, {} FROM {} WHERE {} IN ({}) ORDER BY rowid",
+                        column, COLUMNS, TABLE, column, placeholders
+                    );
+
+                    let mut query = sqlx::query(&statement);
+
+                    for key in chunk {
+                        query = query.bind(key.clone());
+                    }
+
+                    let rows = query.fetch_all(&mut *conn).await?;
+                    let mut values = rows
+                        .iter()
+                        .map(from_row)
+                        .collect::<::std::result::Result<Vec<_>, _>>()?;
+
+                    load_children(conn, &mut values).await?;
+
+                    for (row, value) in rows.iter().zip(values) {
+                        keyed.push((sqlx::Row::try_get(row, 0usize)?, value));
+                    }
+                }
+
+                Ok(keyed)
+            }
+
+            #loaders_by_key
+
+            const INDEXES: &[&str] = &[#(#indexes),*];
+
+            // the column type of fields written with `converter`
+            #[allow(dead_code)]
+            fn sql_type_of<A, T: sqlx::Type<sqlx::Sqlite>>(_converter: fn(A) -> T) -> String {
+                <T as sqlx::Type<sqlx::Sqlite>>::type_info().to_string()
+            }
+
+            /// The sql that creates the table with its indexes, followed by the embedded tables.
+            pub fn create_table_sql() -> String {
+                create_table_sql_referencing(&[])
+            }
+
+            /// `create_table_sql` with `(column, references)` added to the definition of
+            /// `column`, the column is indexed too.
+            #[doc(hidden)]
+            pub fn create_table_sql_referencing(foreign_keys: &[(&str, &str)]) -> String {
+                let columns: Vec<(&str, String, &str)> = vec![#(#column_defs),*];
+
+                let columns = columns
+                    .iter()
+                    .map(|(column, sql_type, constraint)| {
+                        let mut definition = format!("    {} {}{}", column, sql_type, constraint);
+
+                        if let Some((_, references)) = foreign_keys.iter().find(|(key, _)| key == column) {
+                            definition.push(' ');
+                            definition.push_str(references);
+                        }
+
+                        definition
+                    })
+                    .collect::<Vec<_>>();
+
+                let mut sql = format!("CREATE TABLE IF NOT EXISTS {} (\n{}\n);\n", TABLE, columns.join(",\n"));
+
+                for column in INDEXES.iter().chain(foreign_keys.iter().map(|(key, _)| key)) {
+                    sql.push_str(&format!(
+                        "CREATE INDEX IF NOT EXISTS {}_{} ON {} ({});\n",
+                        TABLE, column, TABLE, column
+                    ));
+                }
+
+                #[allow(unused_variables)]
+                let references = #references;
+
+                #(
+                    sql.push('\n');
+                    sql.push_str(&#child_tables);
+                )*
+
+                sql
+            }
         }
     );
 
-    more.into()
+    more
 }
 
 #[derive(Debug, FromMeta, Default)]
 struct FieldArgs {
+    /// Converts a reference to the field into the value that is written.
     #[darling(default)]
     with: Option<String>,
+    /// The inverse of `with` or `embed_translator`, returns a `Result`.
+    #[darling(default)]
+    load_with: Option<String>,
     #[darling(default)]
     embed_with: Option<String>,
     #[darling(default)]
     embed_translator: Option<String>,
+    /// The column of the embedded table that refers to this row.
+    #[darling(default)]
+    foreign_key: Option<String>,
+    /// Overrides the column type `create_table_sql` derives from the rust type.
+    #[darling(default)]
+    sql_type: Option<String>,
+    /// `create_table_sql` creates an index on the column.
+    #[darling(default)]
+    index: bool,
     #[darling(default)]
     skip: bool,
 }
 
+// nullable columns, type aliases of options are not recognized
+fn is_option(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(path) => match path.path.segments.last() {
+            Some(segment) => segment.ident == "Option",
+            None => false,
+        },
+        _ => false,
+    }
+}
+
 #[derive(Debug)]
 struct FieldAttrRemover {
-    fields: Vec<(syn::Ident, FieldArgs)>,
+    fields: Vec<(syn::Ident, syn::Type, FieldArgs)>,
 }
 
 impl FieldAttrRemover {
@@ -210,20 +732,274 @@ impl syn::visit_mut::VisitMut for FieldAttrRemover {
                     }
                 });
 
-        if my_attrs.len() > 0 {
+        if !my_attrs.is_empty() {
             for attr in my_attrs {
                 let field_args = attr.parse_meta().unwrap();
 
                 let args = FieldArgs::from_meta(&field_args).unwrap();
 
-                self.fields.push((name.clone(), args));
+                self.fields.push((name.clone(), field.ty.clone(), args));
 
                 break;
             }
         } else {
-            self.fields.push((name.clone(), Default::default()));
+            self.fields
+                .push((name.clone(), field.ty.clone(), Default::default()));
         }
 
         field.attrs = other_attrs;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::parse_quote;
+
+    fn expand_problem(on_conflict: &str) -> String {
+        let on_conflict = syn::LitStr::new(on_conflict, proc_macro2::Span::call_site());
+        let args: syn::AttributeArgs = vec![
+            parse_quote!(table_name = "problems"),
+            parse_quote!(primary_key = "api_id"),
+            parse_quote!(on_conflict = #on_conflict),
+        ];
+        let item: syn::ItemStruct = parse_quote! {
+            pub struct Problem {
+                pub api_id: i32,
+                pub name: String,
+                #[sqlx_helper::insert(embed_with = "moves", foreign_key = "problem_id")]
+                pub moves: Vec<Move>,
+            }
+        };
+
+        expand(&args, item).to_string()
+    }
+
+    fn position(expansion: &str, part: &str) -> usize {
+        expansion
+            .find(part)
+            .unwrap_or_else(|| panic!("{:?} is not in {}", part, expansion))
+    }
+
+    #[test]
+    fn inserts_the_row_before_its_embedded_rows() {
+        let expansion = expand_problem("update");
+
+        let row = position(&expansion, "INSERT INTO problems");
+        let stale = position(&expansion, "DELETE FROM moves WHERE problem_id = $1");
+        let embedded = position(&expansion, "moves :: insert !");
+
+        assert!(row < stale, "{}", expansion);
+        assert!(stale < embedded, "{}", expansion);
+    }
+
+    #[test]
+    fn updates_rows_on_conflict() {
+        let expansion = expand_problem("update");
+
+        position(
+            &expansion,
+            "INSERT INTO problems (api_id, name) VALUES ($1, $2) \
+             ON CONFLICT(api_id) DO UPDATE SET name = excluded.name",
+        );
+    }
+
+    #[test]
+    fn replaces_rows_on_conflict() {
+        let expansion = expand_problem("replace");
+
+        position(
+            &expansion,
+            "INSERT OR REPLACE INTO problems (api_id, name) VALUES ($1, $2)",
+        );
+        // the embedded rows of the replaced row are removed too
+        position(&expansion, "DELETE FROM moves WHERE problem_id = $1");
+    }
+
+    #[test]
+    #[should_panic(expected = "would insert the embedded moves again")]
+    fn ignoring_conflicts_with_embedded_rows_is_rejected() {
+        expand_problem("ignore");
+    }
+
+    #[test]
+    fn ignores_conflicts() {
+        let args: syn::AttributeArgs = vec![
+            parse_quote!(table_name = "users"),
+            parse_quote!(on_conflict = "ignore"),
+            parse_quote!(conflict_key = "id"),
+        ];
+        let item: syn::ItemStruct = parse_quote! {
+            pub struct User {
+                pub id: i32,
+                pub name: String,
+            }
+        };
+
+        position(
+            &expand(&args, item).to_string(),
+            "INSERT INTO users (id, name) VALUES ($1, $2) ON CONFLICT(id) DO NOTHING",
+        );
+    }
+
+    fn expand_stored_problem() -> String {
+        let args: syn::AttributeArgs = vec![
+            parse_quote!(table_name = "problems"),
+            parse_quote!(primary_key = "api_id"),
+        ];
+        let item: syn::ItemStruct = parse_quote! {
+            pub struct Problem {
+                pub api_id: i32,
+                #[sqlx_helper::insert(index)]
+                pub name: String,
+                pub setter: Option<String>,
+                #[sqlx_helper::insert(with = "date_to_sql", load_with = "date_from_sql")]
+                pub date: Date,
+                #[sqlx_helper::insert(sql_type = "TEXT", with = "grade_to_sql", load_with = "grade_from_sql")]
+                pub grade: Grade,
+                #[sqlx_helper::insert(skip)]
+                pub score: f32,
+                #[sqlx_helper::insert(embed_with = "moves", foreign_key = "problem_id", load_with = "moves_from_rows")]
+                pub moves: Vec<Move>,
+            }
+        };
+
+        expand(&args, item).to_string()
+    }
+
+    #[test]
+    fn declares_the_columns_with_their_constraints() {
+        let expansion = expand_stored_problem();
+
+        position(&expansion, "const TABLE : & str = \"problems\"");
+        position(
+            &expansion,
+            "const COLUMNS : & str = \"api_id, name, setter, date, grade\"",
+        );
+
+        let key = position(&expansion, "( \"api_id\" , < i32 as");
+        let name = position(&expansion, "( \"name\" , < String as");
+        let setter = position(&expansion, "( \"setter\" , < Option < String > as");
+        assert!(position(&expansion[key..], "\" PRIMARY KEY NOT NULL\" )") < name - key);
+        assert!(position(&expansion[name..], "\" NOT NULL\" )") < setter - name);
+        // options are nullable
+        position(
+            &expansion[setter..],
+            ":: type_info ( ) . to_string ( ) , \"\" )",
+        );
+    }
+
+    #[test]
+    fn converted_columns_get_the_type_of_the_converter_unless_overridden() {
+        let expansion = expand_stored_problem();
+
+        position(
+            &expansion,
+            "( \"date\" , sql_type_of ( date_to_sql ) , \" NOT NULL\" )",
+        );
+        position(
+            &expansion,
+            "( \"grade\" , \"TEXT\" . to_string ( ) , \" NOT NULL\" )",
+        );
+    }
+
+    #[test]
+    fn indexes_columns() {
+        let expansion = expand_stored_problem();
+
+        position(&expansion, "const INDEXES : & [ & str ] = & [ \"name\" ]");
+    }
+
+    #[test]
+    fn embedded_tables_reference_their_row() {
+        let expansion = expand_stored_problem();
+
+        position(
+            &expansion,
+            "\"REFERENCES problems (api_id) ON DELETE CASCADE\"",
+        );
+        position(
+            &expansion,
+            "moves :: create_table_sql_referencing ( & [ ( \"problem_id\" , references ) ] )",
+        );
+    }
+
+    #[test]
+    fn loads_rows_by_their_primary_key() {
+        let expansion = expand_stored_problem();
+
+        position(
+            &expansion,
+            "\"SELECT {} FROM {} WHERE {} IN ({}) ORDER BY {}\" , \
+             COLUMNS , TABLE , \"api_id\" , placeholders , \"api_id\"",
+        );
+        position(
+            &expansion,
+            "\"SELECT {} FROM {} ORDER BY {}\" , \"api_id\" , TABLE , \"api_id\"",
+        );
+        position(&expansion, "pub async fn load_by_id");
+    }
+
+    #[test]
+    fn tables_without_a_primary_key_have_no_loaders_by_key() {
+        let args: syn::AttributeArgs = vec![parse_quote!(table_name = "users")];
+        let item: syn::ItemStruct = parse_quote! {
+            pub struct User {
+                pub id: i32,
+            }
+        };
+
+        let expansion = expand(&args, item).to_string();
+
+        assert!(!expansion.contains("load_by_id"), "{}", expansion);
+        assert!(!expansion.contains("load_all"), "{}", expansion);
+    }
+
+    #[test]
+    fn loads_embedded_rows_in_the_order_they_were_inserted() {
+        let expansion = expand_stored_problem();
+
+        position(
+            &expansion,
+            "\"SELECT {}, {} FROM {} WHERE {} IN ({}) ORDER BY rowid\"",
+        );
+        position(
+            &expansion,
+            "moves :: load_embedded ( & mut * conn , \"problem_id\" , & keys )",
+        );
+    }
+
+    #[test]
+    fn loads_converted_fields_with_their_inverse() {
+        let expansion = expand_stored_problem();
+
+        position(
+            &expansion,
+            "date : date_from_sql ( sqlx :: Row :: try_get ( row , \"date\" ) ? )",
+        );
+        position(
+            &expansion,
+            "grade : grade_from_sql ( sqlx :: Row :: try_get ( row , \"grade\" ) ? )",
+        );
+        position(&expansion, ". map ( moves_from_rows )");
+        // what the table doesn't store comes back as the default
+        position(
+            &expansion,
+            "score : :: std :: default :: Default :: default ( )",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "it needs a load_with to be read back")]
+    fn converted_fields_need_a_load_with() {
+        let args: syn::AttributeArgs = vec![parse_quote!(table_name = "users")];
+        let item: syn::ItemStruct = parse_quote! {
+            pub struct User {
+                #[sqlx_helper::insert(with = "date_to_sql")]
+                pub date: Date,
+            }
+        };
+
+        expand(&args, item);
+    }
+}
@@ -0,0 +1,266 @@
+// Serves fixture data like the MoonBoard servers, so the app and the sync can be run
+// without network or real credentials. Point a MoonboardAPI built with
+// `with_endpoints(Endpoints::from_env())`, like record_fixtures, at it with
+// MB_API_URL=http://127.0.0.1:8080 MB_WEBSITE_URL=http://127.0.0.1:8080
+//
+// Needs the mock feature: cargo run --features mock --bin mock_server
+//
+// usage: mock_server [--port PORT] [--fixtures FILE | --problems N] [--page-size N]
+//                    [--page-delay-ms MS] [--token-lifetime-secs SECS]
+//                    [--fail ENDPOINT:STATUS[:TIMES]]...
+//                    [--set-field ENDPOINT:FIELD=JSON]... [--remove-field ENDPOINT:FIELD]...
+//
+// ENDPOINT is one of token, hold_setups, problems, user_search, comments and repeats.
+// The login is MB_USER / MB_PASS, or mock / mock if they are not set.
+// While running, POST /mock/expire_tokens, /mock/revoke_tokens and
+// /mock/fail?endpoint=ENDPOINT&status=STATUS&times=TIMES change the behaviour.
+
+use moonboard::{
+    Fixtures, HttpBody, HttpMethod, HttpRequest, HttpResponse, MockBackend, MockEndpoint,
+    SchemaChange, Transport,
+};
+
+use env_logger::{Builder, Env};
+use failure::{bail, format_err, Error};
+use hyper::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server,
+};
+use log::info;
+
+use std::{collections::HashMap, convert::Infallible, env, sync::Arc, time::Duration};
+
+fn parse_endpoint(endpoint: &str) -> Result<MockEndpoint, Error> {
+    endpoint.parse().map_err(|e: String| format_err!("{}", e))
+}
+
+fn parse_fail(arg: &str) -> Result<(MockEndpoint, u16, usize), Error> {
+    let parts: Vec<&str> = arg.split(':').collect();
+
+    match parts.as_slice() {
+        [endpoint, status] => Ok((parse_endpoint(endpoint)?, status.parse()?, 1)),
+        [endpoint, status, times] => {
+            Ok((parse_endpoint(endpoint)?, status.parse()?, times.parse()?))
+        }
+        _ => bail!("expected ENDPOINT:STATUS[:TIMES], got {}", arg),
+    }
+}
+
+fn parse_field(arg: &str) -> Result<(MockEndpoint, &str), Error> {
+    let mut parts = arg.splitn(2, ':');
+
+    match (parts.next(), parts.next()) {
+        (Some(endpoint), Some(field)) => Ok((parse_endpoint(endpoint)?, field)),
+        _ => bail!("expected ENDPOINT:FIELD, got {}", arg),
+    }
+}
+
+fn parse_args() -> Result<(u16, MockBackend, Vec<(MockEndpoint, u16, usize)>), Error> {
+    let mut args = env::args().skip(1);
+
+    let mut port = 8080;
+    let mut fixtures = None;
+    let mut problems = 2500;
+    let mut page_size = None;
+    let mut page_delay = None;
+    let mut token_lifetime = None;
+    let mut failures = Vec::new();
+    let mut schema_changes = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format_err!("missing value for {}", arg))
+        };
+
+        match arg.as_str() {
+            "--port" => port = value()?.parse()?,
+            "--fixtures" => fixtures = Some(Fixtures::load(value()?)?),
+            "--problems" => problems = value()?.parse()?,
+            "--page-size" => page_size = Some(value()?.parse()?),
+            "--page-delay-ms" => page_delay = Some(Duration::from_millis(value()?.parse()?)),
+            "--token-lifetime-secs" => {
+                token_lifetime = Some(Duration::from_secs(value()?.parse()?))
+            }
+            "--fail" => failures.push(parse_fail(&value()?)?),
+            "--set-field" => {
+                let value = value()?;
+                let mut parts = value.splitn(2, '=');
+                let (endpoint, field) = parse_field(parts.next().unwrap_or(""))?;
+                let json = parts
+                    .next()
+                    .ok_or_else(|| format_err!("expected ENDPOINT:FIELD=JSON, got {}", value))?;
+
+                schema_changes.push(SchemaChange::SetField {
+                    endpoint,
+                    field: field.to_owned(),
+                    value: serde_json::from_str(json)?,
+                });
+            }
+            "--remove-field" => {
+                let value = value()?;
+                let (endpoint, field) = parse_field(&value)?;
+
+                schema_changes.push(SchemaChange::RemoveField {
+                    endpoint,
+                    field: field.to_owned(),
+                });
+            }
+            _ => bail!("unknown argument {}", arg),
+        }
+    }
+
+    let fixtures = fixtures.unwrap_or_else(|| Fixtures::generate(problems));
+    let mut backend = MockBackend::new(fixtures).with_credentials(
+        env::var("MB_USER").unwrap_or_else(|_| "mock".to_owned()),
+        env::var("MB_PASS").unwrap_or_else(|_| "mock".to_owned()),
+    );
+
+    if let Some(page_size) = page_size {
+        backend = backend.with_problem_page_size(page_size);
+    }
+
+    if let Some(page_delay) = page_delay {
+        backend = backend.with_page_delay(page_delay);
+    }
+
+    if let Some(token_lifetime) = token_lifetime {
+        backend = backend.with_token_lifetime(token_lifetime);
+    }
+
+    for change in schema_changes {
+        backend = backend.with_schema_change(change);
+    }
+
+    Ok((port, backend, failures))
+}
+
+// the knobs that can be turned while the server is running
+fn control(backend: &MockBackend, path: &str, query: &str) -> Result<(), Error> {
+    match path {
+        "/mock/expire_tokens" => backend.expire_tokens(),
+        "/mock/revoke_tokens" => backend.revoke_tokens(),
+        "/mock/fail" => {
+            let query: HashMap<String, String> = serde_urlencoded::from_str(query)?;
+            let param = |key: &str| {
+                query
+                    .get(key)
+                    .ok_or_else(|| format_err!("missing query parameter {}", key))
+            };
+
+            let times = query.get("times").map_or(Ok(1), |times| times.parse())?;
+            backend.fail_next(
+                parse_endpoint(param("endpoint")?)?,
+                param("status")?.parse()?,
+                times,
+            );
+        }
+        _ => bail!("unknown control path {}", path),
+    }
+
+    Ok(())
+}
+
+async fn serve(backend: Arc<MockBackend>, request: Request<Body>) -> Result<Response<Body>, Error> {
+    let (parts, body) = request.into_parts();
+    let body = String::from_utf8(hyper::body::to_bytes(body).await?.to_vec())?;
+
+    info!("{} {}", parts.method, parts.uri);
+
+    if parts.uri.path().starts_with("/mock/") {
+        let response = match control(&backend, parts.uri.path(), parts.uri.query().unwrap_or("")) {
+            Ok(()) => Response::new(Body::empty()),
+            Err(e) => Response::builder()
+                .status(400)
+                .body(Body::from(e.to_string()))?,
+        };
+
+        return Ok(response);
+    }
+
+    let method = match parts.method {
+        Method::GET => HttpMethod::Get,
+        Method::POST => HttpMethod::Post,
+        _ => return Ok(Response::builder().status(405).body(Body::empty())?),
+    };
+
+    let content_type = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+
+    let body = if body.is_empty() {
+        HttpBody::Empty
+    } else if content_type.starts_with("application/json") {
+        HttpBody::Json(body)
+    } else {
+        HttpBody::Form(body)
+    };
+
+    let bearer = parts
+        .headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_owned);
+
+    let request = HttpRequest {
+        method,
+        // the backend only looks at the path and query
+        url: format!("http://mock{}", parts.uri),
+        bearer,
+        body,
+    };
+
+    let HttpResponse { status, body } = backend
+        .send(&request)
+        .await
+        .map_err(Error::from_boxed_compat)?;
+
+    Ok(Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json; charset=utf-8")
+        .body(Body::from(body))?)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    Builder::from_env(Env::default().default_filter_or("info"))
+        .format_indent(Some(4))
+        .init();
+
+    let (port, backend, failures) = parse_args()?;
+
+    for (endpoint, status, times) in failures {
+        backend.fail_next(endpoint, status, times);
+    }
+
+    let backend = Arc::new(backend);
+
+    let make_service = make_service_fn(move |_| {
+        let backend = backend.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let backend = backend.clone();
+
+                async move {
+                    serve(backend, request).await.or_else(|e| {
+                        Response::builder()
+                            .status(500)
+                            .body(Body::from(e.to_string()))
+                    })
+                }
+            }))
+        }
+    });
+
+    let addr = ([127, 0, 0, 1], port).into();
+    info!("serving mock MoonBoard api on http://{}", addr);
+
+    Server::bind(&addr).serve(make_service).await?;
+
+    Ok(())
+}
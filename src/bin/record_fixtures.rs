@@ -0,0 +1,85 @@
+// Records a short session against the MoonBoard servers into a fixtures directory, with
+// tokens and personal data scrubbed, so it can be replayed with `ReplayTransport`.
+// MB_API_URL and MB_WEBSITE_URL record from somewhere else, like the mock server.
+//
+// The tests replay fixtures/synthetic_session, which is synthetic, it was recorded from the
+// mock server with the user search "alex". Record into an empty directory to replace it.
+//
+// usage: MB_USER=... MB_PASS=... record_fixtures DIR [USER_SEARCH]
+
+use moonboard::{
+    ApiError, Endpoints, MoonboardAPI, RecordingTransport, ReqwestTransport, SchemaMode,
+};
+
+use chrono::NaiveDate;
+use env_logger::{Builder, Env};
+use failure::{format_err, Error};
+use futures_util::{pin_mut, StreamExt};
+use log::info;
+use std::env;
+
+// requests a sample of every endpoint, only the first page of problems, a full download is
+// thousands of requests. The requests only depend on `user_search` and the recorded
+// responses, so the same session can be replayed from the fixtures, replays_synthetic_session
+// in recording.rs has to be kept in sync with this.
+async fn sample_session(api: &MoonboardAPI, user_search: &str) -> Result<(), ApiError> {
+    // the requests are made outside of the log macros, those skip their arguments when
+    // logging is disabled
+    let holdsetups = api.holdsetups().await?;
+    info!("holdsetups: {}", holdsetups.len());
+
+    let problems = api.all_problems_stream();
+    pin_mut!(problems);
+    let problem = problems
+        .next()
+        .await
+        .ok_or_else(|| ApiError::Protocol("got no problems".to_owned()))??;
+    info!("first problem: {}", problem.api_id);
+
+    let updates = api.problem_updates_stream(
+        NaiveDate::from_ymd(2019, 12, 1).and_hms(0, 0, 0),
+        None,
+        None,
+    )?;
+    pin_mut!(updates);
+    // there might not be any updates, so that is not treated as an error
+    let update = updates.next().await.map(|update| update.map(|p| p.api_id));
+    info!("updated problem: {:?}", update);
+
+    let users = api.search_user(user_search).await?;
+    info!("users: {}", users.len());
+
+    let comments = api.problem_comments(problem.api_id).await?;
+    info!("comments: {}", comments.len());
+
+    let repeats = api.problem_repeats(problem.api_id).await?;
+    info!("repeats: {}", repeats.len());
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    Builder::from_env(Env::default().default_filter_or("info"))
+        .format_indent(Some(4))
+        .init();
+
+    let mut args = env::args().skip(1);
+    let dir = args
+        .next()
+        .ok_or_else(|| format_err!("usage: record_fixtures DIR [USER_SEARCH]"))?;
+    let user_search = args.next().unwrap_or_else(|| "moon".to_owned());
+
+    let transport = RecordingTransport::new(ReqwestTransport::new(), &dir)?;
+    // drift in the responses is what the fixtures are for, so don't stop on it
+    let api = MoonboardAPI::new(env::var("MB_USER")?, env::var("MB_PASS")?)
+        .with_transport(transport)
+        .with_endpoints(Endpoints::from_env())
+        .with_schema_mode(SchemaMode::Lenient);
+
+    sample_session(&api, &user_search).await?;
+
+    info!("recorded into {}", dir);
+
+    Ok(())
+}
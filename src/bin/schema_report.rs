@@ -0,0 +1,33 @@
+// Compares responses of the MoonBoard api with our models, to see what changed upstream.
+// Exits with status 1 if anything differs.
+//
+// usage: schema_report FIXTURES_DIR
+//
+// To check the live api, record a session with record_fixtures first.
+
+use moonboard::SchemaReport;
+
+use env_logger::{Builder, Env};
+use failure::{format_err, Error};
+use std::env;
+
+fn main() -> Result<(), Error> {
+    Builder::from_env(Env::default().default_filter_or("warn"))
+        .format_indent(Some(4))
+        .init();
+
+    let dir = env::args()
+        .nth(1)
+        .ok_or_else(|| format_err!("usage: schema_report FIXTURES_DIR"))?;
+
+    let mut report = SchemaReport::new();
+    report.add_fixtures(&dir)?;
+
+    print!("{}", report);
+
+    if !report.is_clean() {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
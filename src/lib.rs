@@ -1,7 +1,38 @@
 #![feature(async_closure)]
 
+mod migrations;
+pub use migrations::{migrate, schema_version, MigrationError, SCHEMA_VERSION};
 mod moonboard_api;
+// what the binaries, `sync_problems` callers and tools reading the models need, the
+// converters behind the models stay internal
+pub use moonboard_api::{
+    ApiError, CancellationToken, Endpoints, HttpBody, HttpMethod, HttpRequest,
+    HttpResponse, MoonboardAPI, RecordingTransport, ReqwestTransport, SchemaMode, SchemaReport,
+    Transport,
+};
+// configuring the api and its downloads
+pub use moonboard_api::{
+    DownloadCheckpoint, DownloadLimits, DownloadOptions, DownloadProgress, Downloaded, Exchange,
+    FakeTransport, FileTokenStore, MemoryTokenStore, ProgressReporter, ReplayTransport,
+    RetryPolicy, Token, TokenError, TokenStore, TransportError,
+};
+// the problems, users, comments and repeats, `problems` has the loaders of stored problems
+pub use moonboard_api::{
+    problems, BoardSize, BoulderGrade, BoulderMethod, Grade, HoldCoordinate, HoldSetFromProblem,
+    HoldSetupFromProblem, Move, MoveCoordinate, Problem, ProblemID, Rating, RepeatOrComment,
+    ResolvedMove, ResolvedProblem, User, VGrade,
+};
+// the hold setups with their geometry, for drawing and analysing problems
+pub use moonboard_api::{
+    BoardGeometry, BoardHold, Hold, HoldDirection, HoldId, HoldLayoutId, HoldLocation,
+    HoldNumber, HoldRotation, HoldSet, HoldSetID, HoldSetup, HoldSetupID, HoldType,
+    MoonBoardConfiguration, MoonBoardConfigurationID,
+};
+#[cfg(feature = "mock")]
+pub use moonboard_api::{Fixtures, MockBackend, MockEndpoint, SchemaChange};
 mod moonboard;
+mod sync;
+pub use sync::{sync_problems, SyncError, SyncReport, Watermarks};
 pub use moonboard::*;
 
 mod java_glue;
@@ -0,0 +1,167 @@
+use log::info;
+use sqlx::{sqlite::SqliteConnection, Connection, Executor};
+
+use std::fmt;
+
+// applied in order, the schema version of a database is the number of migrations applied to
+// it, so never change or remove one that was released, only add new ones
+// the tables of the insertable structs have to end up as `problems::create_table_sql()` has them
+const MIGRATIONS: &[&str] = &[
+    include_str!("migrations/0001_initial.sql"),
+    include_str!("migrations/0002_sync_watermarks.sql"),
+];
+
+/// The schema version this library creates and understands.
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Database(sqlx::Error),
+    /// The database was written by a newer version of this library.
+    TooNew {
+        version: i64,
+        supported: i64,
+    },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MigrationError::Database(e) => write!(f, "database error while migrating: {}", e),
+            MigrationError::TooNew { version, supported } => write!(
+                f,
+                "database has schema version {}, but we only support up to {}",
+                version, supported
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MigrationError::Database(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for MigrationError {
+    fn from(e: sqlx::Error) -> MigrationError {
+        MigrationError::Database(e)
+    }
+}
+
+/// The schema version of the database, 0 for a new database.
+pub async fn schema_version(conn: &mut SqliteConnection) -> Result<i64, MigrationError> {
+    let (version,): (i64,) = sqlx::query_as("PRAGMA user_version")
+        .fetch_one(&mut *conn)
+        .await?;
+
+    Ok(version)
+}
+
+/// Turns on foreign key checks for `conn` and brings the database up to `SCHEMA_VERSION`,
+/// returns the version it had before.
+///
+/// Every migration runs in its own transaction together with the version bump, so a crash
+/// leaves the database at the last migration that completed.
+pub async fn migrate(conn: &mut SqliteConnection) -> Result<i64, MigrationError> {
+    // sqlite checks foreign keys per connection and ignores this inside of a transaction
+    conn.execute("PRAGMA foreign_keys = ON").await?;
+
+    let version = schema_version(conn).await?;
+
+    if version > SCHEMA_VERSION {
+        return Err(MigrationError::TooNew {
+            version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let target = i as i64 + 1;
+
+        info!("migrating database to schema version {}", target);
+
+        let mut tx = conn.begin().await?;
+        tx.execute(*migration).await?;
+        // pragmas can't take bind parameters
+        tx.execute(&*format!("PRAGMA user_version = {}", target))
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moonboard_api::problems;
+
+    // the columns, indexes and foreign keys of `table` as sqlite reports them
+    async fn schema(conn: &mut SqliteConnection, table: &str) -> Vec<String> {
+        let columns: Vec<(String, String, i64, i64)> = sqlx::query_as(&format!(
+            "SELECT name, type, \"notnull\", pk FROM pragma_table_info('{}') ORDER BY cid",
+            table
+        ))
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap();
+
+        let indexes: Vec<(String, i64, String)> = sqlx::query_as(&format!(
+            "SELECT list.name, list.\"unique\", info.name \
+             FROM pragma_index_list('{}') AS list, pragma_index_info(list.name) AS info \
+             ORDER BY list.name, info.seqno",
+            table
+        ))
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap();
+
+        let foreign_keys: Vec<(String, String, String, String)> = sqlx::query_as(&format!(
+            "SELECT \"table\", \"from\", \"to\", on_delete FROM pragma_foreign_key_list('{}') \
+             ORDER BY id, seq",
+            table
+        ))
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap();
+
+        columns
+            .iter()
+            .map(|column| format!("column {:?}", column))
+            .chain(indexes.iter().map(|index| format!("index {:?}", index)))
+            .chain(
+                foreign_keys
+                    .iter()
+                    .map(|key| format!("foreign key {:?}", key)),
+            )
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn migrations_create_the_tables_of_the_structs() {
+        let mut migrated = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        migrate(&mut migrated).await.unwrap();
+
+        let mut created = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        created
+            .execute(&*problems::create_table_sql())
+            .await
+            .unwrap();
+
+        for table in &["problems", "moves", "holdsets_for_problems"] {
+            let schema_of_structs = schema(&mut created, table).await;
+
+            assert!(!schema_of_structs.is_empty(), "{} was not created", table);
+            assert_eq!(
+                schema(&mut migrated, table).await,
+                schema_of_structs,
+                "{}",
+                table
+            );
+        }
+    }
+}
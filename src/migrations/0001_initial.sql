@@ -0,0 +1,44 @@
+-- the tables the `sqlx_helper::insertable` structs write into, `IF NOT EXISTS` so databases
+-- created before the schema was versioned are adopted as they are
+
+CREATE TABLE IF NOT EXISTS problems (
+    api_id INTEGER PRIMARY KEY NOT NULL,
+    date_deleted TEXT,
+    date_inserted TEXT NOT NULL,
+    date_updated TEXT,
+    downgraded BOOLEAN NOT NULL,
+    grade TEXT NOT NULL,
+    has_beta_video BOOLEAN NOT NULL,
+    holdsetup INTEGER NOT NULL,
+    is_benchmark BOOLEAN NOT NULL,
+    is_master BOOLEAN NOT NULL,
+    method TEXT NOT NULL,
+    moon_board_configuration_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    repeats INTEGER NOT NULL,
+    setby TEXT NOT NULL,
+    setby_id TEXT NOT NULL,
+    upgraded BOOLEAN NOT NULL,
+    user_grade TEXT,
+    user_rating INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS problems_grade ON problems (grade);
+CREATE INDEX IF NOT EXISTS problems_name ON problems (name);
+
+CREATE TABLE IF NOT EXISTS moves (
+    description TEXT NOT NULL,
+    is_end BOOLEAN NOT NULL,
+    is_start BOOLEAN NOT NULL,
+    problem_id INTEGER NOT NULL REFERENCES problems (api_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS moves_problem_id ON moves (problem_id);
+
+CREATE TABLE IF NOT EXISTS holdsets_for_problems (
+    problem_id INTEGER NOT NULL REFERENCES problems (api_id) ON DELETE CASCADE,
+    api_id INTEGER NOT NULL,
+    description TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS holdsets_for_problems_problem_id ON holdsets_for_problems (problem_id);
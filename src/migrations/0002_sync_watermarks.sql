@@ -0,0 +1,8 @@
+-- where the last problem sync stopped, a single row, see `sync_problems`
+
+CREATE TABLE sync_watermarks (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 0),
+    date_inserted TEXT NOT NULL,
+    date_updated TEXT NOT NULL,
+    date_deleted TEXT NOT NULL
+);
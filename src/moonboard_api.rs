@@ -1,20 +1,63 @@
 use chrono::{Date, DateTime, FixedOffset, NaiveDate, NaiveDateTime};
 use epochs;
-use failure::{format_err, Error, Fail};
-use log::{debug, error, info};
-use reqwest::Client;
+use futures_util::stream::Stream;
+use log::{debug, error, info, warn};
 use rgb::RGB8;
 use serde::{
     de::{self, DeserializeOwned},
-    Deserialize, Deserializer, Serialize,
+    Deserialize, Deserializer, Serialize, Serializer,
 };
-
-use std::{cell::RefCell, fmt::Debug, str::FromStr, time::Duration};
+use serde_json::{Map, Value};
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    convert::Infallible,
+    fmt::Debug,
+    str::FromStr,
+    time::Duration,
+};
+use tokio::sync::Mutex;
 use uuid::Uuid;
 
-type Result<T> = std::result::Result<T, Error>;
-
-#[derive(Deserialize, Serialize, Debug)]
+mod checkpoint;
+mod coordinate;
+mod download;
+mod error;
+mod geometry;
+mod grade;
+#[cfg(any(test, feature = "mock"))]
+mod mock;
+mod recording;
+mod retry;
+mod schema;
+mod schema_report;
+mod token_store;
+mod transport;
+pub use checkpoint::DownloadCheckpoint;
+pub use coordinate::{BoardSize, HoldCoordinate};
+use download::{flatten_pages, PagedPager, ProblemPager};
+pub use download::{
+    CancellationToken, DownloadLimits, DownloadOptions, DownloadProgress, Downloaded,
+    ProgressReporter,
+};
+use error::excerpt;
+pub use error::ApiError;
+pub use geometry::{BoardGeometry, BoardHold, ResolvedMove, ResolvedProblem};
+pub use grade::{Grade, VGrade};
+#[cfg(any(test, feature = "mock"))]
+pub use mock::{Fixtures, MockBackend, MockEndpoint, SchemaChange};
+pub use recording::{Exchange, RecordingTransport, ReplayTransport};
+use retry::RateLimiter;
+pub use retry::RetryPolicy;
+use schema::Lenient;
+pub use schema::SchemaMode;
+pub use schema_report::SchemaReport;
+pub use token_store::*;
+pub use transport::*;
+
+type Result<T> = std::result::Result<T, ApiError>;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 enum ClientID {
     #[serde(rename = "com.moonclimbing.mb")]
     Moonclimbing,
@@ -24,6 +67,8 @@ enum ClientID {
 enum GrantType {
     #[serde(rename = "password")]
     Password,
+    #[serde(rename = "refresh_token")]
+    RefreshToken,
 }
 
 #[derive(Serialize, Debug)]
@@ -34,37 +79,86 @@ struct LoginRequest<'a> {
     client_id: ClientID,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Debug)]
+struct RefreshRequest<'a> {
+    refresh_token: &'a str,
+    grant_type: GrantType,
+    client_id: ClientID,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 enum Role {
     #[serde(rename = "MoonBoard User")]
     User,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 enum TokenType {
     #[serde(rename = "bearer")]
     Bearer,
 }
 
-#[derive(Deserialize, Debug, Fail)]
-#[serde(deny_unknown_fields, tag = "error", rename_all = "snake_case")]
-enum TokenError {
-    #[fail(display = "Token error: invalid_grant: {}", error_description)]
-    InvalidGrant { error_description: String },
+/// The body of a rejected token request, see RFC 6749 section 5.2.
+///
+/// Any error code is accepted, and fields other than the two we show are ignored.
+#[derive(Deserialize, Debug)]
+pub struct TokenError {
+    pub error: String,
+    #[serde(default)]
+    pub error_description: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+impl TokenError {
+    // the token endpoint does not always send a proper error body, e.g. from a proxy
+    fn from_response(status: u16, body: &str) -> TokenError {
+        serde_json::from_str(body).unwrap_or_else(|_| TokenError {
+            error: format!("status {}", status),
+            error_description: Some(excerpt(body)),
+        })
+    }
+}
+
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Token error: {}", self.error)?;
+
+        if let Some(description) = &self.error_description {
+            write!(f, ": {}", description)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+// serializes to the same format the api sends us, so stored tokens can be read back
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "PascalCase", deny_unknown_fields)]
-struct Token {
-    #[serde(rename = ".expires", deserialize_with = "de_datetime_from_rfc2822")]
+pub struct Token {
+    #[serde(
+        rename = ".expires",
+        deserialize_with = "de_datetime_from_rfc2822",
+        serialize_with = "ser_datetime_to_rfc2822"
+    )]
     expires: DateTime<FixedOffset>,
-    #[serde(rename = ".issued", deserialize_with = "de_datetime_from_rfc2822")]
+    #[serde(
+        rename = ".issued",
+        deserialize_with = "de_datetime_from_rfc2822",
+        serialize_with = "ser_datetime_to_rfc2822"
+    )]
     issued: DateTime<FixedOffset>,
-    #[serde(deserialize_with = "de_bool_from_str")]
+    #[serde(
+        deserialize_with = "de_bool_from_str",
+        serialize_with = "ser_bool_to_str"
+    )]
     agree_terms: bool,
     firstname: String,
     lastname: String,
-    #[serde(deserialize_with = "de_bool_from_str")]
+    #[serde(
+        deserialize_with = "de_bool_from_str",
+        serialize_with = "ser_bool_to_str"
+    )]
     is_commercial: bool,
     nickname: String,
     role: Role,
@@ -73,7 +167,11 @@ struct Token {
     access_token: String,
     #[serde(rename = "as:client_id")]
     as_client_id: ClientID,
-    #[serde(rename = "expires_in", deserialize_with = "de_duration_seconds")]
+    #[serde(
+        rename = "expires_in",
+        deserialize_with = "de_duration_seconds",
+        serialize_with = "ser_duration_seconds"
+    )]
     expires_in: Duration,
     #[serde(rename = "refresh_token")]
     refresh_token: String,
@@ -113,6 +211,38 @@ where
     )));
 }
 
+fn ser_rgb8_to_string<S>(color: &RGB8, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&format!("#{:02X}{:02X}{:02X}", color.r, color.g, color.b))
+}
+
+fn ser_rgb8_to_string_option<S>(
+    color: &Option<RGB8>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match color {
+        Some(color) => ser_rgb8_to_string(color, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn de_rgb8_from_string_option<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<RGB8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Color(#[serde(deserialize_with = "de_rgb8_from_string")] RGB8);
+
+    Ok(<Option<Color>>::deserialize(deserializer)?.map(|Color(color)| color))
+}
+
 fn de_duration_seconds<'de, D>(deserializer: D) -> std::result::Result<Duration, D::Error>
 where
     D: Deserializer<'de>,
@@ -121,6 +251,16 @@ where
     Ok(Duration::from_secs(seconds))
 }
 
+fn ser_duration_seconds<S>(
+    duration: &Duration,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_u64(duration.as_secs())
+}
+
 fn de_num_from_str<'de, D>(deserializer: D) -> std::result::Result<i32, D::Error>
 where
     D: Deserializer<'de>,
@@ -138,6 +278,13 @@ where
     bool::from_str(&s).map_err(de::Error::custom)
 }
 
+fn ser_bool_to_str<S>(b: &bool, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(if *b { "True" } else { "False" })
+}
+
 fn de_datetime_from_rfc2822<'de, D>(
     deserializer: D,
 ) -> std::result::Result<DateTime<FixedOffset>, D::Error>
@@ -148,6 +295,16 @@ where
     DateTime::parse_from_rfc2822(&s).map_err(serde::de::Error::custom)
 }
 
+fn ser_datetime_to_rfc2822<S>(
+    d: &DateTime<FixedOffset>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&d.to_rfc2822())
+}
+
 fn de_datetime_from_rfc3339_no_tz<'de, D>(
     deserializer: D,
 ) -> std::result::Result<DateTime<FixedOffset>, D::Error>
@@ -186,6 +343,29 @@ where
         .map_or(Ok(None), |r| r.map(Some))
 }
 
+fn ser_datetime_to_rfc3339_no_tz<S>(
+    d: &DateTime<FixedOffset>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&d.naive_utc().format("%Y-%m-%dT%H:%M:%S%.f").to_string())
+}
+
+fn ser_datetime_to_rfc3339_no_tz_option<S>(
+    d: &Option<DateTime<FixedOffset>>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match d {
+        Some(d) => ser_datetime_to_rfc3339_no_tz(d, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
 fn de_datetime_unix_timestamp<'de, D>(
     deserializer: D,
 ) -> std::result::Result<DateTime<FixedOffset>, D::Error>
@@ -201,9 +381,9 @@ where
         .map_err(serde::de::Error::custom)?;
 
     // milli seconds unix timestamp
-    let d = epochs::java(timestamp)
-        .ok_or_else(|| format_err!("could not parse time from timestamp {}", timestamp))
-        .map_err(serde::de::Error::custom)?;
+    let d = epochs::java(timestamp).ok_or_else(|| {
+        serde::de::Error::custom(format!("could not parse time from timestamp {}", timestamp))
+    })?;
 
     Ok(DateTime::from_utc(d, FixedOffset::east(0)))
 }
@@ -220,38 +400,22 @@ where
     Ok(Date::from_utc(d, FixedOffset::east(0)))
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(untagged, deny_unknown_fields)]
-enum SerdeUntaggedResult<A, B> {
-    Ok(A),
-    Err(B),
-}
-
-impl<A, B, C> Into<std::result::Result<A, C>> for SerdeUntaggedResult<A, B>
-where
-    C: From<B>,
-{
-    fn into(self) -> std::result::Result<A, C> {
-        match self {
-            SerdeUntaggedResult::Ok(a) => Ok(a),
-            SerdeUntaggedResult::Err(b) => Err(b.into()),
-        }
-    }
-}
-
 #[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields)]
 pub struct Problems {
     total: i32,
     pub data: Vec<Problem>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[serde(rename_all = "camelCase")]
 pub struct HoldSetFromProblem {
     pub api_id: HoldSetID,
     pub description: String,
-    pub locations: Option<()>,
+    pub locations: Option<Vec<HoldLocation>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[sqlx_helper::insertable(table_name = "holdsets_for_problems")]
@@ -260,95 +424,183 @@ pub struct HoldSetFromProblemWithID {
     pub problem_id: ProblemID,
     pub api_id: HoldSetID,
     pub description: String,
+    /// Not stored, so `None` for holdsets loaded from the database.
     #[sqlx_helper::insert(skip)]
-    pub locations: Option<()>,
+    pub locations: Option<Vec<HoldLocation>>,
 }
 
 #[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[serde(rename_all = "camelCase")]
 pub struct HoldSetupFromProblem {
     pub api_id: HoldSetupID,
     description: String,
-    holdsets: Option<()>,
+    /// The holdsets of the setup as the holdsetup endpoint has them, null in problems, they
+    /// list the holdsets they use in `Problem::holdsets`.
+    holdsets: Option<Vec<HoldSet>>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
 }
 
-type HoldDirection = i32;
-type HoldNumber = String;
-type HoldRotation = i32;
-type HoldType = i32;
-type HoldId = i32;
-
-#[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields, rename_all = "camelCase")]
-struct HoldLocation {
-    color: Option<()>,
-    description: String,
-    direction: HoldDirection,
-    direction_string: String,
+pub type HoldDirection = i32;
+pub type HoldNumber = String;
+pub type HoldRotation = i32;
+pub type HoldType = i32;
+pub type HoldId = i32;
+
+/// Where a hold sits on the board.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HoldLocation {
+    /// Overrides the color of the holdset, mostly null.
+    #[serde(
+        deserialize_with = "de_rgb8_from_string_option",
+        serialize_with = "ser_rgb8_to_string_option"
+    )]
+    pub color: Option<RGB8>,
+    /// The grid coordinate, like `A5`.
+    pub description: String,
+    pub direction: HoldDirection,
+    /// The direction the hold pulls best in, like `N` or `SE`.
+    pub direction_string: String,
     // #[serde(deserialize_with = "de_num_from_str")]
-    hold_number: HoldNumber,
-    id: i32,
-    rotation: HoldRotation,
+    pub hold_number: HoldNumber,
+    pub id: i32,
+    /// In degrees.
+    pub rotation: HoldRotation,
     #[serde(rename = "type")]
-    ty: i32,
-    x: f64,
-    y: f64,
-    holdset: Option<()>,
+    pub ty: i32,
+    /// Position of the hold on the board image.
+    pub x: f64,
+    pub y: f64,
+    /// The holdset this location belongs to, null when it is part of the holdset already.
+    pub holdset: Option<Box<HoldSet>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields, rename_all = "camelCase")]
-struct Hold {
-    hold_type: HoldType,
-    holdset_description: Option<()>,
-    id: HoldId,
-    location: HoldLocation,
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Hold {
+    pub hold_type: HoldType,
+    pub holdset_description: Option<String>,
+    pub id: HoldId,
+    pub location: HoldLocation,
     // #[serde(deserialize_with = "de_num_from_str")]
-    number: HoldNumber,
+    pub number: HoldNumber,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields, rename_all = "camelCase")]
-struct HoldSet {
-    id: HoldSetID,
-    #[serde(deserialize_with = "de_rgb8_from_string")]
-    color: RGB8,
-    api_id: Option<HoldSetID>,
-    description: String,
-    holds: Vec<Hold>,
+/// A set of holds of one color, like the original school holds, bought together.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HoldSet {
+    pub id: HoldSetID,
+    #[serde(
+        deserialize_with = "de_rgb8_from_string",
+        serialize_with = "ser_rgb8_to_string"
+    )]
+    pub color: RGB8,
+    /// The id problems refer to this holdset with.
+    pub api_id: Option<HoldSetID>,
+    pub description: String,
+    pub holds: Vec<Hold>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-type MoonBoardConfigurationID = i32;
+pub type MoonBoardConfigurationID = i32;
 
-#[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields, rename_all = "camelCase")]
-struct MoonBoardConfiguration {
-    description: String,
-    high_grade: BoulderGrade,
-    low_grade: BoulderGrade,
-    id: MoonBoardConfigurationID,
+/// An angle the board can be set up at, with the grades problems for it can have.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MoonBoardConfiguration {
+    pub description: String,
+    pub high_grade: BoulderGrade,
+    pub low_grade: BoulderGrade,
+    pub id: MoonBoardConfigurationID,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-type HoldLayoutId = i32;
+impl MoonBoardConfiguration {
+    /// The grades problems for this configuration can have, from easy to hard.
+    pub fn grades(&self) -> impl Iterator<Item = Grade> {
+        self.low_grade.up_to(self.high_grade)
+    }
 
-#[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields, rename_all = "camelCase")]
+    pub fn allows(&self, grade: Grade) -> bool {
+        self.low_grade <= grade && grade <= self.high_grade
+    }
+}
+
+pub type HoldLayoutId = i32;
+
+/// A board, like the MoonBoard 2016, with all holdsets that can be mounted on it.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct HoldSetup {
-    id: HoldSetupID,
-    is_locked: bool,
-    setby: Option<()>,
-    api_id: Option<HoldSetupID>,
-    description: String,
-    holdsets: Vec<HoldSet>,
-    active: bool,
-    allow_climb_methods: bool,
-    date_deleted: Option<()>,
-    #[serde(deserialize_with = "de_datetime_from_rfc3339_no_tz")]
-    date_inserted: DateTime<FixedOffset>,
-    #[serde(deserialize_with = "de_datetime_from_rfc3339_no_tz")]
-    date_updated: DateTime<FixedOffset>,
-    hold_layout_id: HoldLayoutId,
-    moon_board_configurations: Vec<MoonBoardConfiguration>,
+    pub id: HoldSetupID,
+    pub is_locked: bool,
+    pub setby: Option<String>,
+    /// The id problems refer to this setup with.
+    pub api_id: Option<HoldSetupID>,
+    pub description: String,
+    pub holdsets: Vec<HoldSet>,
+    pub active: bool,
+    pub allow_climb_methods: bool,
+    /// Set once the setup is retired.
+    #[serde(
+        deserialize_with = "de_datetime_from_rfc3339_no_tz_option",
+        serialize_with = "ser_datetime_to_rfc3339_no_tz_option"
+    )]
+    pub date_deleted: Option<DateTime<FixedOffset>>,
+    #[serde(
+        deserialize_with = "de_datetime_from_rfc3339_no_tz",
+        serialize_with = "ser_datetime_to_rfc3339_no_tz"
+    )]
+    pub date_inserted: DateTime<FixedOffset>,
+    #[serde(
+        deserialize_with = "de_datetime_from_rfc3339_no_tz",
+        serialize_with = "ser_datetime_to_rfc3339_no_tz"
+    )]
+    pub date_updated: DateTime<FixedOffset>,
+    pub hold_layout_id: HoldLayoutId,
+    pub moon_board_configurations: Vec<MoonBoardConfiguration>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl HoldSetup {
+    /// Whether the setup was retired, its problems can't be set up on a board anymore.
+    pub fn is_deleted(&self) -> bool {
+        self.date_deleted.is_some()
+    }
+
+    /// All holds of all holdsets of this setup, flattened into their position on the board.
+    pub fn geometry(&self) -> BoardGeometry {
+        BoardGeometry::from_hold_setup(self)
+    }
+
+    /// The smallest board all holds of this setup fit on, the api doesn't tell. `None` if
+    /// the setup has no holds.
+    pub fn board_size(&self) -> Option<BoardSize> {
+        self.holdsets
+            .iter()
+            .flat_map(|holdset| &holdset.holds)
+            .filter_map(|hold| hold.location.description.parse::<HoldCoordinate>().ok())
+            .fold(None, |size, coordinate| {
+                let BoardSize { columns, rows } = size.unwrap_or(BoardSize {
+                    columns: 0,
+                    rows: 0,
+                });
+
+                Some(BoardSize {
+                    columns: columns.max(coordinate.column as u8 - b'A' + 1),
+                    rows: rows.max(coordinate.row),
+                })
+            })
+    }
 }
 
 #[derive(Deserialize, Debug, sqlx::Type)]
@@ -370,44 +622,102 @@ pub enum BoulderMethod {
 
 #[sqlx_helper::insertable(table_name = "moves")]
 #[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[serde(rename_all = "camelCase")]
 pub struct Move {
+    #[sqlx_helper::insert(with = "coordinate_to_string", load_with = "string_to_coordinate")]
     pub description: MoveCoordinate,
     pub is_end: bool,
     pub is_start: bool,
     pub problem_id: ProblemID,
+    #[sqlx_helper::insert(skip)]
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-pub type BoulderGrade = String;
+pub type BoulderGrade = Grade;
 pub type Rating = i32;
-pub type MoveCoordinate = String;
+pub type MoveCoordinate = HoldCoordinate;
 pub type ProblemID = i32;
 pub type HoldSetID = i32;
 pub type HoldSetupID = i32;
 
-pub fn option_date_to_string(d: Option<DateTime<FixedOffset>>) -> Option<String> {
+// the format `DateTime::to_string` writes
+const DATE_STRING_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f %:z";
+
+pub fn option_date_to_string(d: &Option<DateTime<FixedOffset>>) -> Option<String> {
     d.map(|d| d.to_string())
 }
 
-pub fn date_to_string(d: DateTime<FixedOffset>) -> String {
+pub fn date_to_string(d: &DateTime<FixedOffset>) -> String {
     d.to_string()
 }
 
-pub fn setup_id_from_hold_setup(setup: HoldSetupFromProblem) -> HoldSetupID {
+pub fn string_to_date(s: String) -> std::result::Result<DateTime<FixedOffset>, chrono::ParseError> {
+    DateTime::parse_from_str(&s, DATE_STRING_FORMAT)
+}
+
+pub fn option_string_to_date(
+    s: Option<String>,
+) -> std::result::Result<Option<DateTime<FixedOffset>>, chrono::ParseError> {
+    s.map(string_to_date).transpose()
+}
+
+// the font grades sort in grade order as strings too, so sql can compare them
+pub fn grade_to_string(grade: &Grade) -> String {
+    grade.to_string()
+}
+
+pub fn option_grade_to_string(grade: &Option<Grade>) -> Option<String> {
+    grade.map(|grade| grade.to_string())
+}
+
+pub fn string_to_grade(s: String) -> std::result::Result<Grade, String> {
+    s.parse()
+}
+
+pub fn option_string_to_grade(s: Option<String>) -> std::result::Result<Option<Grade>, String> {
+    s.map(string_to_grade).transpose()
+}
+
+pub fn coordinate_to_string(coordinate: &HoldCoordinate) -> String {
+    coordinate.to_string()
+}
+
+pub fn string_to_coordinate(s: String) -> std::result::Result<HoldCoordinate, String> {
+    s.parse()
+}
+
+pub fn setup_id_from_hold_setup(setup: &HoldSetupFromProblem) -> HoldSetupID {
     setup.api_id
 }
 
-pub fn uuid_to_string(uuid: Uuid) -> String {
+/// Only the id of the setup is stored, so the description is empty.
+pub fn hold_setup_from_id(
+    api_id: HoldSetupID,
+) -> std::result::Result<HoldSetupFromProblem, Infallible> {
+    Ok(HoldSetupFromProblem {
+        api_id,
+        description: String::new(),
+        holdsets: None,
+        extra: Map::new(),
+    })
+}
+
+pub fn uuid_to_string(uuid: &Uuid) -> String {
     uuid.to_string()
 }
 
+pub fn string_to_uuid(s: String) -> std::result::Result<Uuid, uuid::Error> {
+    Uuid::parse_str(&s)
+}
+
 pub fn holdset_add_problemid(
     problem: &Problem,
     hold_set: &HoldSetFromProblem,
 ) -> HoldSetFromProblemWithID {
     let api_id = hold_set.api_id;
     let description = hold_set.description.clone();
-    let locations = hold_set.locations;
+    let locations = hold_set.locations.clone();
     let problem_id = problem.api_id;
 
     HoldSetFromProblemWithID {
@@ -418,44 +728,70 @@ pub fn holdset_add_problemid(
     }
 }
 
-#[sqlx_helper::insertable(table_name = "problems")]
+pub fn holdset_remove_problemid(
+    hold_set: HoldSetFromProblemWithID,
+) -> std::result::Result<HoldSetFromProblem, Infallible> {
+    Ok(HoldSetFromProblem {
+        api_id: hold_set.api_id,
+        description: hold_set.description,
+        locations: hold_set.locations,
+        extra: Map::new(),
+    })
+}
+
+/// Problems loaded from the database (`problems::load_by_id`, `problems::load_all`) only have
+/// what is stored: the hold setup has no description, the holdsets have no locations and
+/// `extra` is empty. `resolve_holds` only needs the ids of both, so it works on them too.
+#[sqlx_helper::insertable(
+    table_name = "problems",
+    primary_key = "api_id",
+    on_conflict = "update"
+)]
 #[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[serde(rename_all = "camelCase")]
 pub struct Problem {
     pub api_id: ProblemID,
-    #[sqlx_helper::insert(with = "option_date_to_string")]
+    #[sqlx_helper::insert(with = "option_date_to_string", load_with = "option_string_to_date")]
     #[serde(deserialize_with = "de_datetime_from_rfc3339_no_tz_option")]
     pub date_deleted: Option<DateTime<FixedOffset>>,
-    #[sqlx_helper::insert(with = "date_to_string")]
+    #[sqlx_helper::insert(with = "date_to_string", load_with = "string_to_date")]
     #[serde(deserialize_with = "de_datetime_from_rfc3339_no_tz")]
     pub date_inserted: DateTime<FixedOffset>,
-    #[sqlx_helper::insert(with = "option_date_to_string")]
+    #[sqlx_helper::insert(with = "option_date_to_string", load_with = "option_string_to_date")]
     #[serde(deserialize_with = "de_datetime_from_rfc3339_no_tz_option")]
     pub date_updated: Option<DateTime<FixedOffset>>,
     pub downgraded: bool,
+    #[sqlx_helper::insert(with = "grade_to_string", load_with = "string_to_grade", index)]
     pub grade: BoulderGrade,
     pub has_beta_video: bool,
     #[sqlx_helper::insert(
         embed_with = "holdsets_for_problems",
-        embed_translator = "holdset_add_problemid"
+        embed_translator = "holdset_add_problemid",
+        load_with = "holdset_remove_problemid",
+        foreign_key = "problem_id"
     )]
     pub holdsets: Vec<HoldSetFromProblem>,
-    #[sqlx_helper::insert(with = "setup_id_from_hold_setup")]
+    #[sqlx_helper::insert(with = "setup_id_from_hold_setup", load_with = "hold_setup_from_id")]
     pub holdsetup: HoldSetupFromProblem,
     pub is_benchmark: bool,
     pub is_master: bool,
     pub method: BoulderMethod,
     pub moon_board_configuration_id: i32,
-    #[sqlx_helper::insert(embed_with = "moves")]
+    #[sqlx_helper::insert(embed_with = "moves", foreign_key = "problem_id")]
     pub moves: Vec<Move>,
+    #[sqlx_helper::insert(index)]
     pub name: String,
     pub repeats: i32,
     pub setby: String,
-    #[sqlx_helper::insert(with = "uuid_to_string")]
+    #[sqlx_helper::insert(with = "uuid_to_string", load_with = "string_to_uuid")]
     pub setby_id: Uuid,
     pub upgraded: bool,
+    #[sqlx_helper::insert(with = "option_grade_to_string", load_with = "option_string_to_grade")]
     pub user_grade: Option<BoulderGrade>,
     pub user_rating: Option<Rating>,
+    #[sqlx_helper::insert(skip)]
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[derive(Serialize, Debug)]
@@ -474,9 +810,10 @@ struct UserSearch<'a> {
 type UserStatus = i32;
 
 #[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase", deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
 pub struct User {
-    action_by_moon_id: Option<()>,
+    /// Always null so far, anything else is moved to `extra`.
+    action_by_moon_id: Option<Value>,
     #[serde(deserialize_with = "de_datetime_from_rfc3339_no_tz_option")]
     date_deleted: Option<DateTime<FixedOffset>>,
     #[serde(deserialize_with = "de_datetime_from_rfc3339_no_tz_option")]
@@ -488,6 +825,8 @@ pub struct User {
     lastname: String,
     nickname: String,
     status: UserStatus,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -504,7 +843,7 @@ enum NumberOfTries {
 }
 
 #[derive(Deserialize, Debug)]
-#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+#[serde(rename_all = "PascalCase")]
 pub struct RepeatOrComment {
     comment: Option<String>,
     attempts: i32,
@@ -517,15 +856,19 @@ pub struct RepeatOrComment {
     grade: Option<BoulderGrade>,
     id: i32,
     is_suggested_benchmark: bool,
-    moon_board: Option<()>,
+    /// The board the problem was climbed on, null when we asked for the problem ourselves.
+    moon_board: Option<MoonBoardFromRepeatOrComment>,
     number_of_tries: NumberOfTries,
-    problem: Option<()>,
+    /// The problem that was climbed, null when we asked for the problem ourselves.
+    problem: Option<ProblemFromRepeatOrComment>,
     rating: Option<Rating>,
     user: Option<UserFromRepeatOrComment>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
 }
 
 #[derive(Deserialize, Debug)]
-#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+#[serde(rename_all = "PascalCase")]
 struct UserFromRepeatOrComment {
     can_share_data: bool,
     city: Option<String>,
@@ -535,15 +878,85 @@ struct UserFromRepeatOrComment {
     lastname: String,
     nickname: String,
     profile_image_url: String,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
 }
 
 #[derive(Deserialize, Debug)]
-#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+#[serde(rename_all = "PascalCase")]
+struct MoonBoardFromRepeatOrComment {
+    id: MoonBoardConfigurationID,
+    description: String,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ProblemFromRepeatOrComment {
+    id: ProblemID,
+    name: String,
+    grade: Option<BoulderGrade>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// An aggregate over all elements of a paged request, in the format of the Kendo UI data
+/// source the website uses. We never ask for any, so this is only logged.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct AggregateResult {
+    value: Value,
+    member: Option<String>,
+    formatted_value: Value,
+    item_count: i32,
+    caption: Option<String>,
+    function_name: Option<String>,
+    aggregate_method_name: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// The errors of a paged request, in the format of the Kendo UI data source the website
+/// uses: either the messages grouped by the field they are about, or a single message.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum PagedErrors {
+    ByField(BTreeMap<String, FieldErrors>),
+    Message(String),
+}
+
+#[derive(Deserialize, Debug)]
+struct FieldErrors {
+    errors: Vec<String>,
+}
+
+impl PagedErrors {
+    fn messages(&self) -> Vec<String> {
+        match self {
+            PagedErrors::ByField(fields) => fields
+                .iter()
+                .flat_map(|(field, errors)| {
+                    errors.errors.iter().map(move |error| match field.as_str() {
+                        "" => error.clone(),
+                        field => format!("{}: {}", field, error),
+                    })
+                })
+                .collect(),
+            PagedErrors::Message(message) => vec![message.clone()],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
 struct Paged<T> {
-    aggregate_results: Option<()>,
+    aggregate_results: Option<Vec<AggregateResult>>,
     data: Vec<T>,
-    errors: Option<()>,
+    errors: Option<PagedErrors>,
     total: i32,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
 }
 
 #[derive(Serialize, Debug)]
@@ -579,45 +992,295 @@ impl<'a> PagedQuery<'a> {
 }
 
 pub struct MoonboardAPI {
-    token: RefCell<Option<Token>>,
-    client: Client,
+    token: Mutex<Option<Token>>,
+    token_store: Box<dyn TokenStore>,
+    transport: Box<dyn Transport>,
+    endpoints: Endpoints,
+    retry_policy: RetryPolicy,
+    rate_limiter: RateLimiter,
+    download_limits: DownloadLimits,
+    schema_mode: SchemaMode,
+    // drift locations already warned about in lenient mode
+    reported_drift: std::sync::Mutex<HashSet<String>>,
     username: String,
     password: String,
 }
 
+// the apps share one api between threads, fail to compile if that stops working
+fn _assert_send_sync() {
+    fn assert<T: Send + Sync>() {}
+    assert::<MoonboardAPI>();
+}
+
 const WEBSITE_URL: &str = "https://moonboard.com";
 const API_URL: &str = "https://restapimoonboard.ems-x.com";
 const API_PATH: &str = "v1/_moonapi";
 const PAGE_SIZE: i32 = 1000; // TODO(robin): seems to work for now
+const DEFAULT_MIN_REQUEST_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Base urls of the two servers the data is spread over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoints {
+    /// The rest api of the app, with the login, problems, holdsetups and users.
+    pub api_url: String,
+    /// The website, with the comments and repeats.
+    pub website_url: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Endpoints {
+        Endpoints {
+            api_url: API_URL.to_owned(),
+            website_url: WEBSITE_URL.to_owned(),
+        }
+    }
+}
+
+impl Endpoints {
+    /// The default urls, unless overridden by `MB_API_URL` and `MB_WEBSITE_URL`, for example
+    /// to point a tool at the mock server. Nothing reads these unless it opts in with
+    /// `with_endpoints(Endpoints::from_env())`.
+    pub fn from_env() -> Endpoints {
+        let default = Endpoints::default();
+
+        Endpoints {
+            api_url: std::env::var("MB_API_URL").unwrap_or(default.api_url),
+            website_url: std::env::var("MB_WEBSITE_URL").unwrap_or(default.website_url),
+        }
+    }
+}
 
 macro_rules! api_path {
-    ($fmt: expr $(, $exprs:expr)*) => {
-        format!("{}/{}/{}", API_URL, API_PATH, format!($fmt, $($exprs),*))
+    ($self: expr, $fmt: expr $(, $exprs:expr)*) => {
+        format!("{}/{}/{}", $self.endpoints.api_url, API_PATH, format!($fmt, $($exprs),*))
     };
 }
 
 macro_rules! website_path {
-    ($fmt: expr $(, $exprs:expr)*) => {
-        format!("{}/{}", WEBSITE_URL, format!($fmt, $($exprs),*))
+    ($self: expr, $fmt: expr $(, $exprs:expr)*) => {
+        format!("{}/{}", $self.endpoints.website_url, format!($fmt, $($exprs),*))
     };
 }
 
+impl HttpBody {
+    fn json<B: Serialize>(body: &B) -> Result<HttpBody> {
+        serde_json::to_string(body)
+            .map(HttpBody::Json)
+            .map_err(|e| ApiError::InvalidArgument(e.to_string()))
+    }
+
+    fn form<B: Serialize>(body: &B) -> Result<HttpBody> {
+        serde_urlencoded::to_string(body)
+            .map(HttpBody::Form)
+            .map_err(|e| ApiError::InvalidArgument(e.to_string()))
+    }
+}
+
+impl HttpRequest {
+    fn new(method: HttpMethod, url: &str, body: HttpBody) -> HttpRequest {
+        HttpRequest {
+            method,
+            url: url.to_owned(),
+            bearer: None,
+            body,
+        }
+    }
+}
+
+fn is_success(status: u16) -> bool {
+    (200..300).contains(&status)
+}
+
+fn decode<T: DeserializeOwned>(url: &str, status: u16, body: &str) -> Result<T> {
+    if !is_success(status) {
+        return Err(ApiError::Status {
+            url: url.to_owned(),
+            status,
+            body: excerpt(body),
+        });
+    }
+
+    serde_json::from_str(body).map_err(|source| ApiError::Decode {
+        url: url.to_owned(),
+        source,
+        payload: excerpt(body),
+    })
+}
+
 impl MoonboardAPI {
     pub fn new(username: String, password: String) -> MoonboardAPI {
         MoonboardAPI {
-            token: RefCell::new(None),
-            client: Client::new(),
+            token: Mutex::new(None),
+            token_store: Box::new(MemoryTokenStore::new()),
+            transport: Box::new(ReqwestTransport::new()),
+            endpoints: Endpoints::default(),
+            retry_policy: Default::default(),
+            rate_limiter: RateLimiter::new(DEFAULT_MIN_REQUEST_INTERVAL),
+            download_limits: Default::default(),
+            schema_mode: Default::default(),
+            reported_drift: Default::default(),
             username,
             password,
         }
     }
 
-    async fn refresh_token(&self, _refresh_token: &str) -> Result<Token> {
-        unimplemented!();
+    /// Use `store` to keep the login token across restarts.
+    pub fn with_token_store<S: TokenStore + 'static>(mut self, store: S) -> MoonboardAPI {
+        self.token_store = Box::new(store);
+        self
+    }
+
+    /// Send the requests through `transport` instead of talking to the MoonBoard servers directly.
+    pub fn with_transport<T: Transport + 'static>(mut self, transport: T) -> MoonboardAPI {
+        self.transport = Box::new(transport);
+        self
+    }
+
+    pub fn with_endpoints(mut self, endpoints: Endpoints) -> MoonboardAPI {
+        self.endpoints = endpoints;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> MoonboardAPI {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Space requests at least `min_interval` apart, to not hammer the MoonBoard servers.
+    pub fn with_rate_limit(mut self, min_interval: Duration) -> MoonboardAPI {
+        self.rate_limiter = RateLimiter::new(min_interval);
+        self
+    }
+
+    pub fn with_download_limits(mut self, download_limits: DownloadLimits) -> MoonboardAPI {
+        self.download_limits = download_limits;
+        self
+    }
+
+    pub fn with_schema_mode(mut self, schema_mode: SchemaMode) -> MoonboardAPI {
+        self.schema_mode = schema_mode;
+        self
+    }
+
+    fn decode_model<T: Lenient>(&self, url: &str, value: Value) -> Result<T> {
+        let mut reported = self.reported_drift.lock().unwrap();
+        schema::decode(self.schema_mode, &mut reported, url, value)
+    }
+
+    fn load_token(&self) -> Option<Token> {
+        match self.token_store.load() {
+            Ok(Some(token)) if token.username == self.username => Some(token),
+            Ok(Some(token)) => {
+                info!("ignoring stored token of different user {}", token.username);
+                None
+            }
+            Ok(None) => None,
+            Err(e) => {
+                warn!("could not load stored token: {}", e);
+                None
+            }
+        }
+    }
+
+    fn save_token(&self, token: &Token) {
+        if let Err(e) = self.token_store.store(token) {
+            warn!("could not store token: {}", e);
+        }
+    }
+
+    async fn send(&self, request: &HttpRequest) -> Result<HttpResponse> {
+        self.rate_limiter.wait().await;
+
+        self.transport
+            .send(request)
+            .await
+            .map_err(|source| ApiError::Transport {
+                url: request.url.clone(),
+                source,
+            })
+    }
+
+    /// Sends `request`, retrying transient failures according to the retry policy.
+    async fn execute(&self, request: &HttpRequest) -> Result<HttpResponse> {
+        let mut attempt = 1;
+
+        loop {
+            let outcome = self.send(request).await;
+
+            let failure = match &outcome {
+                Ok(response) if self.retry_policy.retry_on.contains(&response.status) => {
+                    format!("status {}", response.status)
+                }
+                Err(e @ ApiError::Transport { .. }) => e.to_string(),
+                _ => return outcome,
+            };
+
+            if attempt >= self.retry_policy.max_attempts {
+                error!("giving up on {} after {} attempts", request.url, attempt);
+                return outcome;
+            }
+
+            let backoff = self.retry_policy.backoff(attempt);
+            warn!(
+                "request to {} failed ({}), retrying in {:?}",
+                request.url, failure, backoff
+            );
+            tokio::time::delay_for(backoff).await;
+
+            attempt += 1;
+        }
+    }
+
+    async fn execute_authenticated(&self, mut request: HttpRequest) -> Result<HttpResponse> {
+        let bearer = self.bearer_token().await?;
+        request.bearer = Some(bearer.clone());
+        let response = self.execute(&request).await?;
+
+        if response.status == 401 {
+            // the token can be revoked before it expires, so log in again once
+            info!("{} rejected our token, logging in again", request.url);
+            self.invalidate_token(&bearer).await;
+
+            request.bearer = Some(self.bearer_token().await?);
+            return self.execute(&request).await;
+        }
+
+        Ok(response)
+    }
+
+    async fn token_request(&self, url: &str, form: HttpBody) -> Result<Token> {
+        let request = HttpRequest::new(HttpMethod::Post, url, form);
+        let response = self.execute(&request).await?;
+
+        // bad credentials or refresh tokens are rejected with 400, some servers use 401,
+        // whatever the error code in the body is
+        if response.status == 400 || response.status == 401 {
+            return Err(TokenError::from_response(response.status, &response.body).into());
+        }
+
+        decode(url, response.status, &response.body)
+    }
+
+    async fn refresh_token(&self, refresh_token: &str) -> Result<Token> {
+        let refresh_url = format!("{}/token", self.endpoints.api_url);
+
+        let refresh_request = RefreshRequest {
+            refresh_token,
+            client_id: ClientID::Moonclimbing,
+            grant_type: GrantType::RefreshToken,
+        };
+
+        let token = self
+            .token_request(&refresh_url, HttpBody::form(&refresh_request)?)
+            .await?;
+
+        debug!("got refreshed token: {:#?}", token);
+
+        Ok(token)
     }
 
     async fn initial_login(&self) -> Result<Token> {
-        let login_url = format!("{}/token", API_URL);
+        let login_url = format!("{}/token", self.endpoints.api_url);
 
         let login_request = LoginRequest {
             username: &self.username,
@@ -626,139 +1289,200 @@ impl MoonboardAPI {
             grant_type: GrantType::Password,
         };
 
-        let response = self
-            .client
-            .post(&login_url)
-            .form(&login_request)
-            .send()
+        let token = self
+            .token_request(&login_url, HttpBody::form(&login_request)?)
             .await?;
 
-        let token: SerdeUntaggedResult<Token, TokenError> = response.json().await?;
-
         debug!("got initial login token: {:#?}", token);
 
-        token.into()
+        Ok(token)
     }
 
     async fn bearer_token(&self) -> Result<String> {
-        let mut t = self.token.borrow_mut();
+        // the lock is held while logging in or refreshing, so concurrent callers wait
+        // for the one running login instead of starting their own
+        let mut t = self.token.lock().await;
 
         if t.is_none() {
-            *t = Some(self.initial_login().await?);
+            *t = self.load_token();
+        }
+
+        if t.is_none() {
+            let token = self.initial_login().await?;
+            self.save_token(&token);
+            *t = Some(token);
         }
 
         let t = t.as_mut().unwrap();
 
         if t.is_expired() {
-            *t = self.refresh_token(&t.refresh_token).await?;
+            *t = match self.refresh_token(&t.refresh_token).await {
+                Ok(token) => token,
+                // the refresh token itself can expire or be revoked, whenever the token
+                // endpoint rejects it we fall back to a full login, every other error
+                // is passed on
+                Err(ApiError::Auth(token_error)) => {
+                    warn!("refresh token rejected ({}), logging in again", token_error);
+                    self.initial_login().await?
+                }
+                Err(e) => return Err(e),
+            };
+
+            self.save_token(t);
         }
 
         Ok(t.access_token.clone())
     }
 
-    async fn api_get<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
-        info!("api get {}", url);
+    async fn invalidate_token(&self, access_token: &str) {
+        let mut t = self.token.lock().await;
 
-        let response = self
-            .client
-            .get(url)
-            .bearer_auth(self.bearer_token().await?)
-            .send()
-            .await?;
+        // a concurrent request might already have replaced the rejected token
+        if t.as_ref().map_or(false, |t| t.access_token == access_token) {
+            *t = None;
 
-        let parsed: T = response.json().await?;
+            if let Err(e) = self.token_store.clear() {
+                warn!("could not clear stored token: {}", e);
+            }
+        }
+    }
 
-        Ok(parsed)
+    async fn api_get<T: Lenient>(&self, url: &str) -> Result<T> {
+        info!("api get {}", url);
+
+        let request = HttpRequest::new(HttpMethod::Get, url, HttpBody::Empty);
+        let response = self.execute_authenticated(request).await?;
+
+        let value = decode(url, response.status, &response.body)?;
+        self.decode_model(url, value)
     }
 
-    async fn api_post_json<B: Serialize + Debug, T: DeserializeOwned>(
+    async fn api_post_json<B: Serialize + Debug, T: Lenient>(
         &self,
         url: &str,
         body: B,
     ) -> Result<T> {
         info!("api post json {}, body: {:?}", url, body);
 
-        let response = self
-            .client
-            .post(url)
-            .bearer_auth(self.bearer_token().await?)
-            .json(&body)
-            .send()
-            .await?;
-
-        let parsed: T = response.json().await?;
+        let request = HttpRequest::new(HttpMethod::Post, url, HttpBody::json(&body)?);
+        let response = self.execute_authenticated(request).await?;
 
-        Ok(parsed)
+        let value = decode(url, response.status, &response.body)?;
+        self.decode_model(url, value)
     }
 
-    async fn api_post_urlencoded<B: Serialize + Debug, T: DeserializeOwned>(
+    async fn api_post_urlencoded<B: Serialize + Debug, T: Lenient>(
         &self,
         url: &str,
         body: B,
     ) -> Result<T> {
         info!("api post urlencoded {}, body: {:?}", url, body);
 
-        let response = self.client.post(url).form(&body).send().await?;
+        let request = HttpRequest::new(HttpMethod::Post, url, HttpBody::form(&body)?);
+        let response = self.execute(&request).await?;
 
-        // println!("{}", response.text().await?);
-        // unimplemented!()
-
-        let parsed: T = response.json().await?;
-
-        Ok(parsed)
+        let value = decode(url, response.status, &response.body)?;
+        self.decode_model(url, value)
     }
 
     // TODO(robin): this api seems to have atleast two more java timestamps as arguments,
     // but unsure what they do
     // (for example Holdsetup/637086364747630000/637117513200000000 )
     pub async fn holdsetups(&self) -> Result<Vec<HoldSetup>> {
-        self.api_get(&api_path!("Holdsetup")).await
+        self.api_get(&api_path!(self, "Holdsetup")).await
     }
 
-    // TODO(robin): stall detection
-    async fn download_problem(
-        &self,
-        next_url: &dyn Fn(ProblemID) -> String,
-    ) -> Result<Vec<Problem>> {
-        let mut problem_id: ProblemID = 0;
-        let mut all_problems = Vec::new();
+    /// Like `holdsetups`, but without the retired setups.
+    pub async fn current_holdsetups(&self) -> Result<Vec<HoldSetup>> {
+        let mut setups = self.holdsetups().await?;
+        setups.retain(|setup| !setup.is_deleted());
 
-        loop {
-            info!("downloading problems with offset: {}", problem_id);
-            let mut problems: Problems = self.api_get(&next_url(problem_id)).await?;
+        Ok(setups)
+    }
 
-            info!("problems left: {}", problems.total);
+    async fn download_problem<'a>(
+        &'a self,
+        next_url: Box<dyn Fn(ProblemID) -> String + Send + Sync + 'a>,
+        options: DownloadOptions<'a>,
+    ) -> Result<Downloaded<Problem>> {
+        let mut pager = ProblemPager::new(self, next_url);
 
-            // TODO(robin): maybe we want a set over the id?
-            all_problems.append(&mut problems.data);
+        if let Some(cancel) = options.cancel {
+            pager.cancel_on(cancel);
+        }
 
-            problem_id = all_problems
-                .last()
-                .ok_or_else(|| format_err!("Got no problems from problem_id {}", problem_id))?
-                .api_id;
+        if let Some(progress) = options.progress {
+            pager.report_to(progress);
+        }
 
-            if problems.total <= 0 {
-                break;
-            }
+        let mut all_problems = match options.checkpoint {
+            Some(checkpoint) => pager.resume(checkpoint)?,
+            None => Vec::new(),
+        };
+
+        while let Some(mut problems) = pager.next_page().await? {
+            all_problems.append(&mut problems);
         }
 
-        Ok(all_problems)
+        pager.finish()?;
+
+        Ok(Downloaded {
+            items: all_problems,
+            cancelled_at: pager.cancelled_at(),
+        })
     }
 
     pub async fn all_problems(&self) -> Result<Vec<Problem>> {
-        self.download_problem(&|id| api_path!("problems/v2/{}", id))
-            .await
+        // nothing can cancel it, so it is complete
+        Ok(self.all_problems_with(Default::default()).await?.items)
     }
 
-    pub async fn problem_updates(
+    /// Like `all_problems`, but can be resumed, cancelled and report its progress, see
+    /// `DownloadOptions`.
+    pub async fn all_problems_with(
+        &self,
+        options: DownloadOptions<'_>,
+    ) -> Result<Downloaded<Problem>> {
+        self.download_problem(
+            Box::new(|id| api_path!(self, "problems/v2/{}", id)),
+            options,
+        )
+        .await
+    }
+
+    /// Like `all_problems`, but saves every page to `checkpoint` and continues from
+    /// it if a previous download was interrupted.
+    pub async fn all_problems_resumable(
+        &self,
+        checkpoint: &DownloadCheckpoint,
+    ) -> Result<Vec<Problem>> {
+        let options = DownloadOptions {
+            checkpoint: Some(checkpoint),
+            ..Default::default()
+        };
+
+        Ok(self.all_problems_with(options).await?.items)
+    }
+
+    /// Like `all_problems`, but yields the problems page by page as they are downloaded.
+    pub fn all_problems_stream(&self) -> impl Stream<Item = Result<Problem>> + Send + '_ {
+        let pager = ProblemPager::new(
+            self,
+            Box::new(move |id| api_path!(self, "problems/v2/{}", id)),
+        );
+
+        flatten_pages(pager.into_pages())
+    }
+
+    fn updates_url(
         &self,
         date_inserted: NaiveDateTime,
         date_updated: Option<NaiveDateTime>,
         date_deleted: Option<NaiveDateTime>,
-    ) -> Result<Vec<Problem>> {
+    ) -> Result<impl Fn(ProblemID) -> String + Send + Sync + '_> {
         if date_updated.is_none() && date_deleted.is_some() {
-            Err(format_err!(
-                "Got a date_deleted, but no date_updated, that is not possible"
+            Err(ApiError::InvalidArgument(
+                "Got a date_deleted, but no date_updated, that is not possible".to_owned(),
             ))
         } else {
             let mut postfix = format!("/{}", epochs::to_windows_date(date_inserted));
@@ -771,19 +1495,87 @@ impl MoonboardAPI {
                 }
             }
 
-            self.download_problem(&|id| {
-                let mut url = api_path!("problems/v2/{}", id);
+            Ok(move |id| {
+                let mut url = api_path!(self, "problems/v2/{}", id);
                 url.push_str(&postfix);
 
                 url
             })
-            .await
         }
     }
 
+    pub async fn problem_updates(
+        &self,
+        date_inserted: NaiveDateTime,
+        date_updated: Option<NaiveDateTime>,
+        date_deleted: Option<NaiveDateTime>,
+    ) -> Result<Vec<Problem>> {
+        let downloaded = self
+            .problem_updates_with(
+                Default::default(),
+                date_inserted,
+                date_updated,
+                date_deleted,
+            )
+            .await?;
+
+        Ok(downloaded.items)
+    }
+
+    /// Like `problem_updates`, but can be resumed, cancelled and report its progress, see
+    /// `DownloadOptions`.
+    pub async fn problem_updates_with(
+        &self,
+        options: DownloadOptions<'_>,
+        date_inserted: NaiveDateTime,
+        date_updated: Option<NaiveDateTime>,
+        date_deleted: Option<NaiveDateTime>,
+    ) -> Result<Downloaded<Problem>> {
+        let next_url = self.updates_url(date_inserted, date_updated, date_deleted)?;
+
+        self.download_problem(Box::new(next_url), options).await
+    }
+
+    /// Like `problem_updates`, but saves every page to `checkpoint` and continues from
+    /// it if a previous download was interrupted.
+    pub async fn problem_updates_resumable(
+        &self,
+        checkpoint: &DownloadCheckpoint,
+        date_inserted: NaiveDateTime,
+        date_updated: Option<NaiveDateTime>,
+        date_deleted: Option<NaiveDateTime>,
+    ) -> Result<Vec<Problem>> {
+        let options = DownloadOptions {
+            checkpoint: Some(checkpoint),
+            ..Default::default()
+        };
+
+        let downloaded = self
+            .problem_updates_with(options, date_inserted, date_updated, date_deleted)
+            .await?;
+
+        Ok(downloaded.items)
+    }
+
+    /// Like `problem_updates`, but yields the problems page by page as they are downloaded.
+    pub fn problem_updates_stream(
+        &self,
+        date_inserted: NaiveDateTime,
+        date_updated: Option<NaiveDateTime>,
+        date_deleted: Option<NaiveDateTime>,
+    ) -> Result<impl Stream<Item = Result<Problem>> + Send + '_> {
+        let next_url = self.updates_url(date_inserted, date_updated, date_deleted)?;
+        let pager = ProblemPager::new(self, Box::new(next_url));
+
+        Ok(flatten_pages(pager.into_pages()))
+    }
+
     pub async fn search_user(&self, pattern: &str) -> Result<Vec<User>> {
-        self.api_post_json(&api_path!("Users/Search"), &UserSearch { name: pattern })
-            .await
+        self.api_post_json(
+            &api_path!(self, "Users/Search"),
+            &UserSearch { name: pattern },
+        )
+        .await
     }
 
     pub async fn all_users(&self) -> Result<Vec<User>> {
@@ -791,59 +1583,527 @@ impl MoonboardAPI {
         self.search_user("").await
     }
 
-    async fn download_paged<'a, T: DeserializeOwned>(
-        &self,
+    async fn download_paged<'a, T: Lenient>(
+        &'a self,
         url: String,
-        next_query: &dyn Fn(i32) -> PagedQuery<'a>,
-    ) -> Result<Vec<T>> {
-        let mut page = 1;
-        let mut total = 0;
-        let mut all_elems = Vec::new();
-
-        loop {
-            info!("downloading page {}", page);
+        next_query: Box<dyn Fn(i32) -> PagedQuery<'a> + Send + Sync + 'a>,
+        options: DownloadOptions<'a>,
+    ) -> Result<Downloaded<T>> {
+        if options.checkpoint.is_some() {
+            return Err(ApiError::InvalidArgument(
+                "only problem downloads can be resumed from a checkpoint".to_owned(),
+            ));
+        }
 
-            let mut elems: Paged<T> = self.api_post_urlencoded(&url, &next_query(page)).await?;
+        let mut pager = PagedPager::new(self, url, next_query);
 
-            if let Some(errors) = elems.errors {
-                error!("error while downloading page: {:?}", errors);
-            }
-
-            if let Some(aggregate_results) = elems.aggregate_results {
-                info!("aggregate_results: {:?}", aggregate_results);
-            }
+        if let Some(cancel) = options.cancel {
+            pager.cancel_on(cancel);
+        }
 
-            total = total.max(elems.total);
-            info!("new total: {}", elems.total);
+        if let Some(progress) = options.progress {
+            pager.report_to(progress);
+        }
 
-            all_elems.append(&mut elems.data);
+        let mut all_elems = Vec::new();
 
-            if all_elems.len() >= total as usize {
-                break;
-            } else {
-                page += 1;
-            }
+        while let Some(mut elems) = pager.next_page().await? {
+            all_elems.append(&mut elems);
         }
 
-        Ok(all_elems)
+        Ok(Downloaded {
+            items: all_elems,
+            cancelled_at: pager.cancelled_at(),
+        })
     }
 
     pub async fn problem_comments(&self, id: ProblemID) -> Result<Vec<RepeatOrComment>> {
+        Ok(self.problem_comments_with(id, Default::default()).await?.items)
+    }
+
+    /// Like `problem_comments`, but stops between two pages once `cancel` is cancelled and
+    /// returns what it got until then.
+    pub async fn problem_comments_cancellable(
+        &self,
+        id: ProblemID,
+        cancel: &CancellationToken,
+    ) -> Result<Downloaded<RepeatOrComment>> {
+        let options = DownloadOptions {
+            cancel: Some(cancel),
+            ..Default::default()
+        };
+
+        self.problem_comments_with(id, options).await
+    }
+
+    /// Like `problem_comments`, but can be cancelled and report its progress, see
+    /// `DownloadOptions`. Fails if a checkpoint is given.
+    pub async fn problem_comments_with(
+        &self,
+        id: ProblemID,
+        options: DownloadOptions<'_>,
+    ) -> Result<Downloaded<RepeatOrComment>> {
         info!("downloading comments of problem {}", id);
 
         self.download_paged(
-            website_path!("Problems/GetComments?problemId={}", id),
-            &PagedQuery::comments_query,
+            website_path!(self, "Problems/GetComments?problemId={}", id),
+            Box::new(PagedQuery::comments_query),
+            options,
         )
         .await
     }
 
     pub async fn problem_repeats(&self, id: ProblemID) -> Result<Vec<RepeatOrComment>> {
+        Ok(self.problem_repeats_with(id, Default::default()).await?.items)
+    }
+
+    /// Like `problem_repeats`, but stops between two pages once `cancel` is cancelled and
+    /// returns what it got until then.
+    pub async fn problem_repeats_cancellable(
+        &self,
+        id: ProblemID,
+        cancel: &CancellationToken,
+    ) -> Result<Downloaded<RepeatOrComment>> {
+        let options = DownloadOptions {
+            cancel: Some(cancel),
+            ..Default::default()
+        };
+
+        self.problem_repeats_with(id, options).await
+    }
+
+    /// Like `problem_repeats`, but can be cancelled and report its progress, see
+    /// `DownloadOptions`. Fails if a checkpoint is given.
+    pub async fn problem_repeats_with(
+        &self,
+        id: ProblemID,
+        options: DownloadOptions<'_>,
+    ) -> Result<Downloaded<RepeatOrComment>> {
         info!("downloading repeats of problem {}", id);
 
-        self.download_paged(website_path!("Problems/GetRepeats"), &|page| {
-            PagedQuery::repeats_query(page, id)
-        })
+        self.download_paged(
+            website_path!(self, "Problems/GetRepeats"),
+            Box::new(move |page| PagedQuery::repeats_query(page, id)),
+            options,
+        )
         .await
     }
+
+    pub fn problem_comments_stream(
+        &self,
+        id: ProblemID,
+    ) -> impl Stream<Item = Result<RepeatOrComment>> + Send + '_ {
+        let pager = PagedPager::new(
+            self,
+            website_path!(self, "Problems/GetComments?problemId={}", id),
+            Box::new(PagedQuery::comments_query),
+        );
+
+        flatten_pages(pager.into_pages())
+    }
+
+    pub fn problem_repeats_stream(
+        &self,
+        id: ProblemID,
+    ) -> impl Stream<Item = Result<RepeatOrComment>> + Send + '_ {
+        let pager = PagedPager::new(
+            self,
+            website_path!(self, "Problems/GetRepeats"),
+            Box::new(move |page| PagedQuery::repeats_query(page, id)),
+        );
+
+        flatten_pages(pager.into_pages())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use futures_util::future::BoxFuture;
+    use serde_json::json;
+    use std::{collections::HashMap, sync::Arc};
+
+    /// A token as the login returns it, for `username` "mock".
+    pub(crate) fn token(access_token: &str, expires: DateTime<Utc>) -> Token {
+        serde_json::from_value(json!({
+            ".expires": expires.to_rfc2822(),
+            ".issued": (expires - chrono::Duration::weeks(2)).to_rfc2822(),
+            "AgreeTerms": "True",
+            "Firstname": "Mock",
+            "Lastname": "User",
+            "IsCommercial": "False",
+            "Nickname": "mock",
+            "Role": "MoonBoard User",
+            "UserId": "2b7a5c1e-8d4f-4e1a-9c3b-7f6e5d4c3b2a",
+            "access_token": access_token,
+            "as:client_id": "com.moonclimbing.mb",
+            "expires_in": 1_209_599,
+            "refresh_token": format!("refresh-{}", access_token),
+            "token_type": "bearer",
+            "userName": "mock"
+        }))
+        .unwrap()
+    }
+
+    pub(crate) fn api<T: Transport + 'static>(transport: T) -> MoonboardAPI {
+        MoonboardAPI::new("mock".to_owned(), "mock".to_owned())
+            .with_transport(transport)
+            .with_endpoints(Endpoints {
+                api_url: "http://mock".to_owned(),
+                website_url: "http://mock".to_owned(),
+            })
+            .with_rate_limit(Duration::from_secs(0))
+    }
+
+    /// Passes requests on to a transport and remembers them.
+    pub(crate) struct Recorded<T> {
+        inner: T,
+        requests: std::sync::Mutex<Vec<HttpRequest>>,
+    }
+
+    impl<T> Recorded<T> {
+        pub(crate) fn new(inner: T) -> Arc<Recorded<T>> {
+            Arc::new(Recorded {
+                inner,
+                requests: Default::default(),
+            })
+        }
+
+        pub(crate) fn inner(&self) -> &T {
+            &self.inner
+        }
+
+        pub(crate) fn requests(&self) -> Vec<HttpRequest> {
+            self.requests.lock().unwrap().clone()
+        }
+
+        /// The `grant_type` of every token request, in order.
+        fn grants(&self) -> Vec<String> {
+            self.requests()
+                .iter()
+                .filter_map(|request| match &request.body {
+                    HttpBody::Form(form) if request.url.ends_with("/token") => {
+                        serde_urlencoded::from_str::<HashMap<String, String>>(form)
+                            .ok()?
+                            .remove("grant_type")
+                    }
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
+    impl<T: Transport> Transport for Recorded<T> {
+        fn send<'a>(
+            &'a self,
+            request: &'a HttpRequest,
+        ) -> BoxFuture<'a, std::result::Result<HttpResponse, TransportError>> {
+            self.requests.lock().unwrap().push(request.clone());
+            self.inner.send(request)
+        }
+    }
+
+    fn mock_backend() -> MockBackend {
+        MockBackend::new(Fixtures::generate(3))
+    }
+
+    #[tokio::test]
+    async fn refreshes_expired_token() {
+        let backend = Recorded::new(mock_backend().with_token_lifetime(Duration::from_secs(1)));
+        let api = api(backend.clone());
+
+        api.holdsetups().await.unwrap();
+        tokio::time::delay_for(Duration::from_millis(1100)).await;
+        api.holdsetups().await.unwrap();
+
+        assert_eq!(backend.grants(), ["password", "refresh_token"]);
+    }
+
+    #[tokio::test]
+    async fn logs_in_again_when_refresh_token_is_rejected() {
+        let backend = Recorded::new(mock_backend().with_token_lifetime(Duration::from_secs(1)));
+        let api = api(backend.clone());
+
+        api.holdsetups().await.unwrap();
+        backend.inner().revoke_tokens();
+        tokio::time::delay_for(Duration::from_millis(1100)).await;
+        api.holdsetups().await.unwrap();
+
+        assert_eq!(backend.grants(), ["password", "refresh_token", "password"]);
+    }
+
+    #[tokio::test]
+    async fn logs_in_again_when_token_is_revoked_before_it_expires() {
+        let backend = Recorded::new(mock_backend());
+        let api = api(backend.clone());
+
+        api.holdsetups().await.unwrap();
+        backend.inner().expire_tokens();
+        api.holdsetups().await.unwrap();
+
+        assert_eq!(backend.grants(), ["password", "password"]);
+    }
+
+    #[tokio::test]
+    async fn rejected_login_is_an_auth_error() {
+        let backend = mock_backend().with_credentials("mock".to_owned(), "other".to_owned());
+
+        match api(backend).holdsetups().await {
+            Err(ApiError::Auth(e)) => assert_eq!(e.error, "invalid_grant"),
+            other => panic!("expected an auth error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn any_rejected_refresh_falls_back_to_login() {
+        let rejections = [
+            (
+                400,
+                r#"{"error": "invalid_client", "error_description": "Client is inactive.", "error_uri": "https://example.com"}"#,
+            ),
+            (400, r#"{"error": "unauthorized_client"}"#),
+            (401, "<html>Unauthorized</html>"),
+        ];
+
+        for &(status, body) in rejections.iter() {
+            let transport = Arc::new(FakeTransport::new());
+            transport
+                .respond(HttpMethod::Post, "http://mock/token", status, body)
+                .respond(
+                    HttpMethod::Post,
+                    "http://mock/token",
+                    200,
+                    serde_json::to_string(&token("new", Utc::now() + chrono::Duration::weeks(2)))
+                        .unwrap(),
+                )
+                .respond(
+                    HttpMethod::Get,
+                    "http://mock/v1/_moonapi/Holdsetup",
+                    200,
+                    "[]",
+                );
+
+            let store = MemoryTokenStore::new();
+            store
+                .store(&token("old", Utc::now() - chrono::Duration::hours(1)))
+                .unwrap();
+
+            let api = api(transport.clone()).with_token_store(store);
+            api.holdsetups().await.unwrap();
+
+            let requests = transport.requests();
+            assert_eq!(requests.len(), 3, "{}", body);
+            assert_eq!(requests[2].bearer.as_deref(), Some("new"));
+        }
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn concurrent_requests_share_one_login() {
+        let backend = Recorded::new(mock_backend());
+        let api = Arc::new(api(backend.clone()));
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let api = api.clone();
+                tokio::spawn(async move { api.holdsetups().await.map(|setups| setups.len()) })
+            })
+            .collect();
+
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        assert_eq!(backend.grants(), ["password"]);
+        assert_eq!(backend.requests().len(), 9);
+    }
+
+    // an api that is already logged in, so only the requests under test are sent
+    fn fake_api(transport: Arc<FakeTransport>) -> MoonboardAPI {
+        let store = MemoryTokenStore::new();
+        store
+            .store(&token("fake", Utc::now() + chrono::Duration::weeks(2)))
+            .unwrap();
+
+        api(transport).with_token_store(store)
+    }
+
+    fn paged(data: &[Value], total: usize) -> String {
+        json!({ "AggregateResults": null, "Data": data, "Errors": null, "Total": total })
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn gets_holdsetups() {
+        let fixtures = Fixtures::generate(1);
+        let transport = Arc::new(FakeTransport::new());
+        transport.respond(
+            HttpMethod::Get,
+            "http://mock/v1/_moonapi/Holdsetup",
+            200,
+            Value::Array(fixtures.holdsetups).to_string(),
+        );
+
+        let setups = fake_api(transport.clone()).holdsetups().await.unwrap();
+
+        assert_eq!(setups.len(), 1);
+        assert_eq!(transport.requests()[0].bearer.as_deref(), Some("fake"));
+    }
+
+    #[tokio::test]
+    async fn pages_through_problems_by_cursor() {
+        let fixtures = Fixtures::generate(3);
+        let transport = Arc::new(FakeTransport::new());
+        transport
+            .respond(
+                HttpMethod::Get,
+                "http://mock/v1/_moonapi/problems/v2/0",
+                200,
+                json!({ "total": 1, "data": &fixtures.problems[..2] }).to_string(),
+            )
+            .respond(
+                HttpMethod::Get,
+                "http://mock/v1/_moonapi/problems/v2/2",
+                200,
+                json!({ "total": 0, "data": &fixtures.problems[2..] }).to_string(),
+            );
+
+        let problems = fake_api(transport.clone()).all_problems().await.unwrap();
+        let ids: Vec<_> = problems.iter().map(|problem| problem.api_id).collect();
+
+        assert_eq!(ids, [1, 2, 3]);
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn problem_updates_pass_windows_dates() {
+        let date = NaiveDate::from_ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let windows_date = epochs::to_windows_date(date);
+        let url = format!(
+            "http://mock/v1/_moonapi/problems/v2/0/{}/{}",
+            windows_date, windows_date
+        );
+
+        let fixtures = Fixtures::generate(1);
+        let transport = Arc::new(FakeTransport::new());
+        transport.respond(
+            HttpMethod::Get,
+            &url,
+            200,
+            json!({ "total": 0, "data": fixtures.problems }).to_string(),
+        );
+
+        let api = fake_api(transport.clone());
+        let problems = api.problem_updates(date, Some(date), None).await.unwrap();
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!(transport.requests()[0].url, url);
+
+        match api.problem_updates(date, None, Some(date)).await {
+            Err(ApiError::InvalidArgument(_)) => (),
+            other => panic!("expected an invalid argument, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn problem_updates_without_changes_are_empty() {
+        let date = NaiveDate::from_ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let url = format!(
+            "http://mock/v1/_moonapi/problems/v2/0/{}",
+            epochs::to_windows_date(date)
+        );
+
+        let transport = Arc::new(FakeTransport::new());
+        transport.respond(HttpMethod::Get, &url, 200, r#"{"total": 0, "data": []}"#);
+
+        let api = fake_api(transport);
+        let problems = api.problem_updates(date, None, None).await.unwrap();
+
+        assert!(problems.is_empty());
+    }
+
+    #[tokio::test]
+    async fn searches_users_by_posted_query() {
+        let fixtures = Fixtures::generate(1);
+        let transport = Arc::new(FakeTransport::new());
+        transport
+            .respond(
+                HttpMethod::Post,
+                "http://mock/v1/_moonapi/Users/Search",
+                200,
+                "[]",
+            )
+            .respond_to_body(
+                HttpMethod::Post,
+                "http://mock/v1/_moonapi/Users/Search",
+                HttpBody::Json(r#"{"Query":"alex"}"#.to_owned()),
+                200,
+                json!(&fixtures.users[..1]).to_string(),
+            );
+
+        let api = fake_api(transport);
+
+        assert_eq!(api.search_user("alex").await.unwrap().len(), 1);
+        assert_eq!(api.search_user("bob").await.unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn pages_through_repeats_by_posted_page() {
+        let fixtures = Fixtures::generate(2);
+        let repeats = [&fixtures.repeats[&1][0], &fixtures.repeats[&2][0]];
+        let url = "http://mock/Problems/GetRepeats";
+
+        let transport = Arc::new(FakeTransport::new());
+        for (page, repeat) in repeats.iter().enumerate() {
+            let query = PagedQuery::repeats_query(page as i32 + 1, 7);
+
+            transport.respond_to_body(
+                HttpMethod::Post,
+                url,
+                HttpBody::form(&query).unwrap(),
+                200,
+                paged(&[(*repeat).clone()], 2),
+            );
+        }
+
+        let repeats = fake_api(transport.clone())
+            .problem_repeats(7)
+            .await
+            .unwrap();
+
+        assert_eq!(repeats.len(), 2);
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 2);
+        assert_ne!(requests[0].body, requests[1].body);
+        // the website is not behind the api login
+        assert_eq!(requests[0].bearer, None);
+    }
+
+    #[tokio::test]
+    async fn pages_through_comments() {
+        let fixtures = Fixtures::generate(1);
+        let url = "http://mock/Problems/GetComments?problemId=1";
+
+        let transport = Arc::new(FakeTransport::new());
+        transport.respond(HttpMethod::Post, url, 200, paged(&fixtures.comments[&1], 1));
+
+        let comments = fake_api(transport).problem_comments(1).await.unwrap();
+
+        assert_eq!(comments.len(), 1);
+    }
+
+    #[test]
+    fn token_errors_are_parsed_leniently() {
+        let e = TokenError::from_response(
+            400,
+            r#"{"error": "invalid_client", "error_uri": "https://example.com"}"#,
+        );
+        assert_eq!(e.error, "invalid_client");
+        assert_eq!(e.error_description, None);
+
+        let e = TokenError::from_response(401, "Unauthorized");
+        assert_eq!(e.error, "status 401");
+        assert_eq!(e.to_string(), "Token error: status 401: Unauthorized");
+    }
 }
@@ -0,0 +1,258 @@
+use super::ProblemID;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+/// Progress of a problem download that was interrupted.
+pub(crate) struct SavedDownload {
+    pub(crate) cursor: ProblemID,
+    pub(crate) total: i32,
+    pub(crate) problems: Vec<Value>,
+}
+
+// a crash can leave the last line without its newline, even when it is complete
+fn ends_with_newline(file: &mut fs::File) -> io::Result<bool> {
+    let len = file.metadata()?.len();
+
+    if len == 0 {
+        return Ok(true);
+    }
+
+    let mut last = [0];
+    file.seek(SeekFrom::Start(len - 1))?;
+    file.read_exact(&mut last)?;
+
+    Ok(last[0] == b'\n')
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    download: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Page<P> {
+    cursor: ProblemID,
+    total: i32,
+    problems: P,
+}
+
+/// Remembers the pages of a problem download, so it can be resumed after a crash.
+///
+/// The file is a header line followed by one json line per page, so saving a page
+/// is a single append and a crash while writing can only damage the last line.
+#[derive(Debug)]
+pub struct DownloadCheckpoint {
+    path: PathBuf,
+}
+
+impl DownloadCheckpoint {
+    pub fn new<P: Into<PathBuf>>(path: P) -> DownloadCheckpoint {
+        DownloadCheckpoint { path: path.into() }
+    }
+
+    /// Loads the saved pages, if the checkpoint belongs to `download`.
+    pub(crate) fn load(&self, download: &str) -> io::Result<Option<SavedDownload>> {
+        let file = match fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let mut reader = BufReader::new(&file);
+        let mut line = String::new();
+
+        reader.read_line(&mut line)?;
+        match serde_json::from_str::<Header>(&line) {
+            Ok(header) if header.download == download => {}
+            Ok(header) => {
+                warn!(
+                    "ignoring checkpoint {} of different download {}",
+                    self.path.display(),
+                    header.download
+                );
+                return Ok(None);
+            }
+            Err(e) => {
+                warn!(
+                    "ignoring corrupted checkpoint {}: {}",
+                    self.path.display(),
+                    e
+                );
+                return Ok(None);
+            }
+        }
+
+        let mut valid_len = line.len() as u64;
+        let mut saved: Option<SavedDownload> = None;
+
+        loop {
+            line.clear();
+
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+
+            match serde_json::from_str::<Page<Vec<Value>>>(&line) {
+                Ok(page) => {
+                    valid_len += line.len() as u64;
+
+                    let saved = saved.get_or_insert_with(|| SavedDownload {
+                        cursor: 0,
+                        total: 0,
+                        problems: Vec::new(),
+                    });
+                    saved.cursor = page.cursor;
+                    saved.total = page.total;
+                    saved.problems.extend(page.problems);
+                }
+                Err(e) => {
+                    // cut off the page we crashed writing, so new pages are appended after
+                    // the last good one
+                    warn!("dropping damaged checkpoint page: {}", e);
+                    file.set_len(valid_len)?;
+                    break;
+                }
+            }
+        }
+
+        Ok(saved)
+    }
+
+    pub(crate) fn start(&self, download: &str) -> io::Result<()> {
+        let mut file = fs::File::create(&self.path)?;
+
+        serde_json::to_writer(
+            &mut file,
+            &Header {
+                download: download.to_owned(),
+            },
+        )?;
+        file.write_all(b"\n")?;
+        file.sync_all()
+    }
+
+    pub(crate) fn append(
+        &self,
+        cursor: ProblemID,
+        total: i32,
+        problems: &[Value],
+    ) -> io::Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+
+        // the page goes on a line of its own, after the last complete one
+        let mut line = Vec::new();
+        if !ends_with_newline(&mut file)? {
+            line.push(b'\n');
+        }
+
+        serde_json::to_writer(
+            &mut line,
+            &Page {
+                cursor,
+                total,
+                problems,
+            },
+        )?;
+        line.push(b'\n');
+
+        file.write_all(&line)?;
+        file.sync_data()
+    }
+
+    pub(crate) fn finish(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // a fresh path in the temp dir, the file itself is not created
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "moonboard-checkpoint-{}-{}.jsonl",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn checkpoint_with_pages(name: &str) -> DownloadCheckpoint {
+        let checkpoint = DownloadCheckpoint::new(temp_path(name));
+        checkpoint.start("problems").unwrap();
+        checkpoint.append(1, 3, &[json!(1)]).unwrap();
+        checkpoint.append(2, 3, &[json!(2)]).unwrap();
+        checkpoint
+    }
+
+    #[test]
+    fn truncates_a_torn_last_page() {
+        let checkpoint = checkpoint_with_pages("torn");
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(&checkpoint.path)
+            .unwrap();
+        file.write_all(br#"{"cursor":3,"total":3,"probl"#).unwrap();
+
+        let saved = checkpoint.load("problems").unwrap().unwrap();
+        assert_eq!(
+            (saved.cursor, saved.problems),
+            (2, vec![json!(1), json!(2)])
+        );
+
+        // the next page follows the last good one
+        checkpoint.append(3, 3, &[json!(3)]).unwrap();
+        let saved = checkpoint.load("problems").unwrap().unwrap();
+        assert_eq!(saved.problems, vec![json!(1), json!(2), json!(3)]);
+
+        checkpoint.finish().unwrap();
+    }
+
+    #[test]
+    fn ignores_the_checkpoint_of_another_download() {
+        let checkpoint = checkpoint_with_pages("header");
+
+        assert!(checkpoint.load("updates").unwrap().is_none());
+
+        checkpoint.finish().unwrap();
+    }
+
+    #[test]
+    fn appends_after_a_last_page_without_newline() {
+        let checkpoint = checkpoint_with_pages("newline");
+        let contents = fs::read_to_string(&checkpoint.path).unwrap();
+        fs::write(&checkpoint.path, contents.trim_end()).unwrap();
+
+        let saved = checkpoint.load("problems").unwrap().unwrap();
+        assert_eq!(saved.cursor, 2);
+
+        checkpoint.append(3, 3, &[json!(3)]).unwrap();
+        let saved = checkpoint.load("problems").unwrap().unwrap();
+        assert_eq!(
+            (saved.cursor, saved.problems),
+            (3, vec![json!(1), json!(2), json!(3)])
+        );
+
+        checkpoint.finish().unwrap();
+    }
+}
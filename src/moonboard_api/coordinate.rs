@@ -0,0 +1,257 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use std::{fmt, str::FromStr};
+
+/// The number of hold positions of a board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BoardSize {
+    pub columns: u8,
+    pub rows: u8,
+}
+
+impl BoardSize {
+    /// The full size board, columns `A` to `K` and rows `1` to `18`.
+    pub const MOONBOARD: BoardSize = BoardSize {
+        columns: 11,
+        rows: 18,
+    };
+
+    pub fn contains(&self, coordinate: HoldCoordinate) -> bool {
+        coordinate.column >= 'A'
+            && coordinate.column_index() < self.columns as u32
+            && coordinate.row >= 1
+            && coordinate.row <= self.rows
+    }
+
+    /// The number of hold positions, which is also the number of leds.
+    pub fn positions(&self) -> usize {
+        self.columns as usize * self.rows as usize
+    }
+}
+
+impl Default for BoardSize {
+    fn default() -> BoardSize {
+        BoardSize::MOONBOARD
+    }
+}
+
+/// A hold position on the board as it is written on it, like `A5` or `K18`.
+///
+/// Ordered by column first, then row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HoldCoordinate {
+    /// `A` is the leftmost column.
+    pub column: char,
+    /// `1` is the bottom row.
+    pub row: u8,
+}
+
+impl HoldCoordinate {
+    pub fn new(column: char, row: u8, size: BoardSize) -> Result<HoldCoordinate, String> {
+        let coordinate = HoldCoordinate { column, row };
+
+        if size.contains(coordinate) {
+            Ok(coordinate)
+        } else {
+            Err(format!(
+                "{} is not on a board of {} columns and {} rows",
+                coordinate, size.columns, size.rows
+            ))
+        }
+    }
+
+    /// Parses `s` and checks it is on a board of `size`.
+    pub fn parse(s: &str, size: BoardSize) -> Result<HoldCoordinate, String> {
+        let coordinate: HoldCoordinate = s.parse()?;
+        HoldCoordinate::new(coordinate.column, coordinate.row, size)
+    }
+
+    // zero based, only meaningful for columns from `A` on
+    fn column_index(&self) -> u32 {
+        (self.column as u32).saturating_sub('A' as u32)
+    }
+
+    /// The index of the led under this position.
+    ///
+    /// This assumes one strip that runs up the first column, down the second one and so on,
+    /// so the bottom left hold is led 0 and every other column is counted from the top. That
+    /// is how the boards we have seen are wired, it is not documented anywhere.
+    pub fn to_led(&self, size: BoardSize) -> Option<usize> {
+        if !size.contains(*self) {
+            return None;
+        }
+
+        let column = self.column_index() as usize;
+        let row = self.row as usize - 1;
+        let rows = size.rows as usize;
+
+        let offset = if column % 2 == 0 { row } else { rows - 1 - row };
+
+        Some(column * rows + offset)
+    }
+
+    /// The inverse of `to_led`.
+    pub fn from_led(led: usize, size: BoardSize) -> Option<HoldCoordinate> {
+        if led >= size.positions() {
+            return None;
+        }
+
+        let rows = size.rows as usize;
+        let column = led / rows;
+        let offset = led % rows;
+
+        let row = if column % 2 == 0 {
+            offset
+        } else {
+            rows - 1 - offset
+        };
+
+        Some(HoldCoordinate {
+            column: std::char::from_u32('A' as u32 + column as u32)?,
+            row: row as u8 + 1,
+        })
+    }
+}
+
+impl fmt::Display for HoldCoordinate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.column, self.row)
+    }
+}
+
+/// Parses a coordinate on a board of any size, with columns `A` to `Z` and rows from `1`,
+/// use `HoldCoordinate::parse` to check it is on a board.
+impl FromStr for HoldCoordinate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HoldCoordinate, String> {
+        let mut chars = s.chars();
+
+        let column = match chars.next() {
+            Some(column) if column.is_ascii_uppercase() => column,
+            _ => return Err(format!("invalid hold coordinate {:?}", s)),
+        };
+
+        let row = chars.as_str();
+
+        // `parse` would also take a leading `+`
+        if !row.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("invalid hold coordinate {:?}", s));
+        }
+
+        match row.parse::<u8>() {
+            Ok(row) if row >= 1 => Ok(HoldCoordinate { column, row }),
+            _ => Err(format!("invalid hold coordinate {:?}", s)),
+        }
+    }
+}
+
+impl Serialize for HoldCoordinate {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+/// Only takes coordinates on the largest board, `BoardSize::MOONBOARD`, the size of the
+/// board a problem is for is only known with its hold setup, see `Problem::validate_moves`.
+impl<'de> Deserialize<'de> for HoldCoordinate {
+    fn deserialize<D>(deserializer: D) -> Result<HoldCoordinate, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        HoldCoordinate::parse(&s, BoardSize::MOONBOARD).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coordinate(s: &str) -> HoldCoordinate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_coordinates() {
+        assert_eq!(
+            coordinate("K18"),
+            HoldCoordinate {
+                column: 'K',
+                row: 18
+            }
+        );
+        assert_eq!(coordinate("L19").to_string(), "L19");
+
+        for invalid in &["", "A", "a5", "A0", "A+5", "5A", "A256"] {
+            assert!(invalid.parse::<HoldCoordinate>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn checks_coordinates_against_the_board() {
+        let mini = BoardSize {
+            columns: 11,
+            rows: 12,
+        };
+
+        assert!(HoldCoordinate::parse("K18", BoardSize::MOONBOARD).is_ok());
+        assert!(HoldCoordinate::parse("L1", BoardSize::MOONBOARD).is_err());
+        assert!(HoldCoordinate::parse("A19", BoardSize::MOONBOARD).is_err());
+        assert!(HoldCoordinate::parse("K12", mini).is_ok());
+        assert!(HoldCoordinate::parse("K13", mini).is_err());
+    }
+
+    #[test]
+    fn deserializes_coordinates_on_the_board_only() {
+        let k18: HoldCoordinate = serde_json::from_str(r#""K18""#).unwrap();
+        assert_eq!(k18, coordinate("K18"));
+
+        for invalid in &[r#""Z99""#, r#""L1""#, r#""A19""#, r#""a5""#] {
+            assert!(
+                serde_json::from_str::<HoldCoordinate>(invalid).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn orders_by_column_then_row() {
+        assert!(coordinate("A18") < coordinate("B1"));
+        assert!(coordinate("B1") < coordinate("B2"));
+    }
+
+    #[test]
+    fn maps_coordinates_to_leds() {
+        let size = BoardSize::MOONBOARD;
+
+        for (s, led) in &[
+            ("A1", 0),
+            ("A18", 17),
+            ("B18", 18),
+            ("B1", 35),
+            ("K1", 180),
+            ("K18", 197),
+        ] {
+            assert_eq!(coordinate(s).to_led(size), Some(*led), "{}", s);
+            assert_eq!(HoldCoordinate::from_led(*led, size), Some(coordinate(s)));
+        }
+
+        assert_eq!(coordinate("L1").to_led(size), None);
+        assert_eq!(HoldCoordinate::from_led(198, size), None);
+    }
+
+    #[test]
+    fn leds_round_trip() {
+        let size = BoardSize::MOONBOARD;
+
+        for led in 0..size.positions() {
+            let coordinate = HoldCoordinate::from_led(led, size).unwrap();
+            assert_eq!(coordinate.to_led(size), Some(led));
+        }
+    }
+}
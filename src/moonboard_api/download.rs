@@ -0,0 +1,691 @@
+use super::{
+    schema::{unknown_fields, Drift, DriftReport, Lenient},
+    ApiError, DownloadCheckpoint, MoonboardAPI, Paged, PagedQuery, Problem, ProblemID, Result,
+};
+use futures_util::stream::{self, Stream, StreamExt};
+use log::info;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use std::{
+    collections::HashSet,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Stops running downloads cleanly between two pages.
+///
+/// Clones share the same state, so one can be handed to the download and another
+/// kept around to cancel it.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        Default::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// What a download that can be cancelled got.
+#[derive(Debug)]
+pub struct Downloaded<T> {
+    /// Everything downloaded until the download completed or was cancelled.
+    pub items: Vec<T>,
+    /// The problem id or page number the download was cancelled at, `None` if it completed.
+    pub cancelled_at: Option<i32>,
+}
+
+impl<T> Downloaded<T> {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled_at.is_some()
+    }
+}
+
+/// How a download is run.
+#[derive(Clone, Copy, Default)]
+pub struct DownloadOptions<'a> {
+    /// Save every page here and continue from it if a previous download was interrupted.
+    /// Only problem downloads can be resumed.
+    pub checkpoint: Option<&'a DownloadCheckpoint>,
+    /// Stop between two pages once cancelled. The download then returns what it got so far,
+    /// see `Downloaded`, and keeps the checkpoint, so it can be resumed later.
+    pub cancel: Option<&'a CancellationToken>,
+    /// Gets the progress of this download after every page.
+    pub progress: Option<&'a dyn ProgressReporter>,
+}
+
+impl fmt::Debug for DownloadOptions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DownloadOptions")
+            .field("checkpoint", &self.checkpoint)
+            .field("cancel", &self.cancel)
+            .field("progress", &self.progress.map(|_| "..."))
+            .finish()
+    }
+}
+
+fn is_cancelled(cancel: Option<&CancellationToken>) -> bool {
+    cancel.map_or(false, CancellationToken::is_cancelled)
+}
+
+/// Upper bounds for a single paginated download, so a misbehaving server can't keep
+/// us downloading forever.
+#[derive(Debug, Clone)]
+pub struct DownloadLimits {
+    pub max_pages: Option<u32>,
+    pub time_budget: Option<Duration>,
+}
+
+impl Default for DownloadLimits {
+    fn default() -> DownloadLimits {
+        DownloadLimits {
+            max_pages: Some(1000),
+            time_budget: None,
+        }
+    }
+}
+
+/// State of a running paginated download, reported after every page.
+#[derive(Debug, Clone)]
+pub struct DownloadProgress {
+    /// Url of the first page, identifies the download.
+    pub download: String,
+    pub pages: u32,
+    /// Elements downloaded so far, including those from a resumed checkpoint.
+    pub items: usize,
+    /// The total the server reported for the last page, for problem downloads this
+    /// is the number of problems left, for everything else the number of all elements.
+    pub server_total: i32,
+    /// Elements still to download according to `server_total`.
+    pub estimated_remaining: usize,
+}
+
+/// Receives progress updates of downloads, for example to draw a progress bar.
+///
+/// Implemented for closures, so sending the progress to a channel is just
+/// `move |progress: &DownloadProgress| { let _ = sender.send(progress.clone()); }`.
+pub trait ProgressReporter: Send + Sync {
+    fn report(&self, progress: &DownloadProgress);
+}
+
+impl<F: Fn(&DownloadProgress) + Send + Sync> ProgressReporter for F {
+    fn report(&self, progress: &DownloadProgress) {
+        self(progress)
+    }
+}
+
+struct Budget {
+    limits: DownloadLimits,
+    started: Instant,
+    pages: u32,
+}
+
+impl Budget {
+    fn new(limits: DownloadLimits) -> Budget {
+        Budget {
+            limits,
+            started: Instant::now(),
+            pages: 0,
+        }
+    }
+
+    /// Accounts for the next page, fails if that would exceed the limits.
+    fn next_page(&mut self, url: &str, cursor: i32) -> Result<()> {
+        self.pages += 1;
+
+        let reason = match (self.limits.max_pages, self.limits.time_budget) {
+            (Some(max_pages), _) if self.pages > max_pages => {
+                format!("exceeded the maximum of {} pages", max_pages)
+            }
+            (_, Some(budget)) if self.started.elapsed() > budget => {
+                format!("exceeded the time budget of {:?}", budget)
+            }
+            _ => return Ok(()),
+        };
+
+        Err(stalled(url, cursor, reason))
+    }
+}
+
+/// Turns a stream of pages into a stream of their elements, ending after the first error.
+pub(super) fn flatten_pages<'a, T: 'a>(
+    pages: impl Stream<Item = Result<Vec<T>>> + 'a,
+) -> impl Stream<Item = Result<T>> + 'a {
+    pages
+        .map(|page| {
+            let elems: Vec<Result<T>> = match page {
+                Ok(elems) => elems.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            };
+
+            stream::iter(elems)
+        })
+        .flatten()
+}
+
+fn stalled(url: &str, cursor: i32, reason: String) -> ApiError {
+    ApiError::Stalled {
+        url: url.to_owned(),
+        cursor,
+        reason,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct RawProblems {
+    total: i32,
+    data: Vec<Value>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+// the problems themselves are checked once they are decoded
+impl Drift for RawProblems {
+    fn drift(&mut self, path: &str, report: &mut DriftReport) {
+        unknown_fields(path, &self.extra, report);
+    }
+
+    fn keep(&mut self, location: String, value: Value) {
+        self.extra.insert(location, value);
+    }
+}
+
+impl Lenient for RawProblems {}
+
+/// Walks through the pages of a problem download, one `next_page` call per page.
+///
+/// The api returns the problems after a cursor (the last `api_id` we got) and
+/// how many are still left after this page.
+pub(super) struct ProblemPager<'a> {
+    api: &'a MoonboardAPI,
+    next_url: Box<dyn Fn(ProblemID) -> String + Send + Sync + 'a>,
+    checkpoint: Option<&'a DownloadCheckpoint>,
+    cancel: Option<&'a CancellationToken>,
+    progress: Option<&'a dyn ProgressReporter>,
+    budget: Budget,
+    cursor: ProblemID,
+    seen: HashSet<ProblemID>,
+    received: usize,
+    finished: bool,
+    cancelled_at: Option<ProblemID>,
+}
+
+impl<'a> ProblemPager<'a> {
+    pub(super) fn new(
+        api: &'a MoonboardAPI,
+        next_url: Box<dyn Fn(ProblemID) -> String + Send + Sync + 'a>,
+    ) -> ProblemPager<'a> {
+        ProblemPager {
+            api,
+            next_url,
+            checkpoint: None,
+            cancel: None,
+            progress: None,
+            budget: Budget::new(api.download_limits.clone()),
+            cursor: 0,
+            seen: HashSet::new(),
+            received: 0,
+            finished: false,
+            cancelled_at: None,
+        }
+    }
+
+    fn download(&self) -> String {
+        (self.next_url)(0)
+    }
+
+    /// Continues the download saved in `checkpoint` (if any) and saves every following page to it.
+    /// Returns the problems that were downloaded before.
+    pub(super) fn resume(&mut self, checkpoint: &'a DownloadCheckpoint) -> Result<Vec<Problem>> {
+        let download = self.download();
+        let mut problems = Vec::new();
+
+        match checkpoint.load(&download).map_err(ApiError::Checkpoint)? {
+            Some(saved) => {
+                info!(
+                    "resuming download with {} problems from offset: {}",
+                    saved.problems.len(),
+                    saved.cursor
+                );
+
+                let saved_problems = self
+                    .api
+                    .decode_model(&download, Value::Array(saved.problems))?;
+
+                problems = self.dedup(saved_problems);
+                self.received = problems.len();
+                self.cursor = saved.cursor;
+                self.finished = saved.total <= 0;
+            }
+            None => checkpoint.start(&download).map_err(ApiError::Checkpoint)?,
+        }
+
+        self.checkpoint = Some(checkpoint);
+
+        Ok(problems)
+    }
+
+    pub(super) fn cancel_on(&mut self, cancel: &'a CancellationToken) {
+        self.cancel = Some(cancel);
+    }
+
+    pub(super) fn report_to(&mut self, progress: &'a dyn ProgressReporter) {
+        self.progress = Some(progress);
+    }
+
+    /// Where the download was cancelled, once `next_page` stopped because of it.
+    pub(super) fn cancelled_at(&self) -> Option<i32> {
+        self.cancelled_at
+    }
+
+    /// Removes the checkpoint once the download is complete.
+    pub(super) fn finish(&self) -> Result<()> {
+        match self.checkpoint {
+            Some(checkpoint) if self.finished => checkpoint.finish().map_err(ApiError::Checkpoint),
+            _ => Ok(()),
+        }
+    }
+
+    pub(super) async fn next_page(&mut self) -> Result<Option<Vec<Problem>>> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        if is_cancelled(self.cancel) {
+            info!("download cancelled at offset: {}", self.cursor);
+            self.cancelled_at = Some(self.cursor);
+            return Ok(None);
+        }
+
+        info!("downloading problems with offset: {}", self.cursor);
+
+        let url = (self.next_url)(self.cursor);
+        self.budget.next_page(&url, self.cursor)?;
+
+        let page: RawProblems = self.api.api_get(&url).await?;
+
+        info!("problems left: {}", page.total);
+
+        let problems: Vec<Problem> = self
+            .api
+            .decode_model(&url, Value::Array(page.data.clone()))?;
+
+        let cursor = match problems.last() {
+            Some(problem) => problem.api_id,
+            // nothing (left) to download, e.g. no problem changed since the last update
+            None if page.total <= 0 => {
+                info!("no problems after offset: {}", self.cursor);
+                self.finished = true;
+                return Ok(None);
+            }
+            None => {
+                return Err(ApiError::Protocol(format!(
+                    "Got no problems from problem_id {}",
+                    self.cursor
+                )))
+            }
+        };
+
+        let problems = self.dedup(problems);
+
+        // a cursor that doesn't move would give us the same page over and over again
+        if page.total > 0 && (cursor == self.cursor || problems.is_empty()) {
+            return Err(stalled(
+                &url,
+                self.cursor,
+                format!("no new problems, server returned cursor {}", cursor),
+            ));
+        }
+
+        if let Some(checkpoint) = self.checkpoint {
+            checkpoint
+                .append(cursor, page.total, &page.data)
+                .map_err(ApiError::Checkpoint)?;
+        }
+
+        self.cursor = cursor;
+        self.received += problems.len();
+        self.finished = page.total <= 0;
+
+        if let Some(progress) = self.progress {
+            progress.report(&DownloadProgress {
+                download: self.download(),
+                pages: self.budget.pages,
+                items: self.received,
+                server_total: page.total,
+                estimated_remaining: page.total.max(0) as usize,
+            });
+        }
+
+        Ok(Some(problems))
+    }
+
+    pub(super) fn into_pages(self) -> impl Stream<Item = Result<Vec<Problem>>> + 'a {
+        stream::unfold(Some(self), |pager| async move {
+            let mut pager = pager?;
+
+            match pager.next_page().await {
+                Ok(Some(problems)) => Some((Ok(problems), Some(pager))),
+                Ok(None) => pager.finish().err().map(|e| (Err(e), None)),
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+
+    // the api can send a problem again (for example the one at the cursor), so only
+    // the first copy of every problem is kept
+    fn dedup(&mut self, mut problems: Vec<Problem>) -> Vec<Problem> {
+        let seen = &mut self.seen;
+        problems.retain(|problem| seen.insert(problem.api_id));
+
+        problems
+    }
+}
+
+/// Walks through the pages of a download that uses page numbers and reports the total
+/// number of elements.
+pub(super) struct PagedPager<'a, T> {
+    api: &'a MoonboardAPI,
+    url: String,
+    next_query: Box<dyn Fn(i32) -> PagedQuery<'a> + Send + Sync + 'a>,
+    cancel: Option<&'a CancellationToken>,
+    progress: Option<&'a dyn ProgressReporter>,
+    budget: Budget,
+    page: i32,
+    total: i32,
+    received: usize,
+    finished: bool,
+    cancelled_at: Option<i32>,
+    _elems: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<'a, T: Lenient> PagedPager<'a, T> {
+    pub(super) fn new(
+        api: &'a MoonboardAPI,
+        url: String,
+        next_query: Box<dyn Fn(i32) -> PagedQuery<'a> + Send + Sync + 'a>,
+    ) -> PagedPager<'a, T> {
+        PagedPager {
+            api,
+            url,
+            next_query,
+            cancel: None,
+            progress: None,
+            budget: Budget::new(api.download_limits.clone()),
+            page: 1,
+            total: 0,
+            received: 0,
+            finished: false,
+            cancelled_at: None,
+            _elems: std::marker::PhantomData,
+        }
+    }
+
+    pub(super) fn cancel_on(&mut self, cancel: &'a CancellationToken) {
+        self.cancel = Some(cancel);
+    }
+
+    pub(super) fn report_to(&mut self, progress: &'a dyn ProgressReporter) {
+        self.progress = Some(progress);
+    }
+
+    /// Where the download was cancelled, once `next_page` stopped because of it.
+    pub(super) fn cancelled_at(&self) -> Option<i32> {
+        self.cancelled_at
+    }
+
+    pub(super) async fn next_page(&mut self) -> Result<Option<Vec<T>>> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        if is_cancelled(self.cancel) {
+            info!("download cancelled at page {}", self.page);
+            self.cancelled_at = Some(self.page);
+            return Ok(None);
+        }
+
+        info!("downloading page {}", self.page);
+
+        self.budget.next_page(&self.url, self.page)?;
+
+        let elems: Paged<T> = self
+            .api
+            .api_post_urlencoded(&self.url, &(self.next_query)(self.page))
+            .await?;
+
+        if let Some(errors) = elems.errors {
+            let errors = errors.messages();
+
+            if !errors.is_empty() {
+                return Err(ApiError::Remote {
+                    url: self.url.clone(),
+                    errors,
+                });
+            }
+        }
+
+        if let Some(aggregate_results) = elems.aggregate_results {
+            info!("aggregate_results: {:?}", aggregate_results);
+        }
+
+        self.total = self.total.max(elems.total);
+        info!("new total: {}", elems.total);
+
+        // an empty page before we got everything means we would ask for the same page forever
+        if elems.data.is_empty() && self.received < self.total as usize {
+            return Err(stalled(
+                &self.url,
+                self.page,
+                format!(
+                    "empty page after {} of {} elements",
+                    self.received, self.total
+                ),
+            ));
+        }
+
+        self.received += elems.data.len();
+        self.finished = self.received >= self.total as usize;
+        self.page += 1;
+
+        if let Some(progress) = self.progress {
+            progress.report(&DownloadProgress {
+                download: self.url.clone(),
+                pages: self.budget.pages,
+                items: self.received,
+                server_total: self.total,
+                estimated_remaining: (self.total.max(0) as usize).saturating_sub(self.received),
+            });
+        }
+
+        Ok(Some(elems.data))
+    }
+
+    pub(super) fn into_pages(self) -> impl Stream<Item = Result<Vec<T>>> + 'a
+    where
+        T: 'a,
+    {
+        stream::unfold(Some(self), |pager| async move {
+            let mut pager = pager?;
+
+            match pager.next_page().await {
+                Ok(Some(elems)) => Some((Ok(elems), Some(pager))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moonboard_api::{
+        tests::api, FakeTransport, Fixtures, HttpMethod, MockBackend, RepeatOrComment,
+    };
+
+    fn backend() -> MockBackend {
+        MockBackend::new(Fixtures::generate(5)).with_problem_page_size(2)
+    }
+
+    fn expect_stalled<T: std::fmt::Debug>(result: Result<T>, expected_reason: &str) {
+        match result {
+            Err(ApiError::Stalled { reason, .. }) => {
+                assert!(reason.contains(expected_reason), "{}", reason)
+            }
+            other => panic!("expected a stalled download, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn downloads_all_pages() {
+        let api = api(backend()).with_download_limits(DownloadLimits {
+            max_pages: Some(3),
+            time_budget: None,
+        });
+
+        assert_eq!(api.all_problems().await.unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn reports_progress_of_one_download() {
+        let reported = std::sync::Mutex::new(Vec::new());
+        let progress = |progress: &DownloadProgress| {
+            reported
+                .lock()
+                .unwrap()
+                .push((progress.items, progress.estimated_remaining))
+        };
+
+        let api = api(backend());
+        api.all_problems_with(DownloadOptions {
+            progress: Some(&progress),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        // only the download it was passed to reports to it
+        api.all_problems().await.unwrap();
+
+        assert_eq!(*reported.lock().unwrap(), [(2, 3), (4, 1), (5, 0)]);
+    }
+
+    #[tokio::test]
+    async fn cancelled_download_can_be_resumed() {
+        let path =
+            std::env::temp_dir().join(format!("moonboard-cancelled-{}.jsonl", std::process::id()));
+        let checkpoint = DownloadCheckpoint::new(&path);
+        let cancel = CancellationToken::new();
+        // cancel right after the first page
+        let progress = |_: &DownloadProgress| cancel.cancel();
+
+        let api = api(backend());
+        let cancelled = api
+            .all_problems_with(DownloadOptions {
+                checkpoint: Some(&checkpoint),
+                cancel: Some(&cancel),
+                progress: Some(&progress),
+            })
+            .await
+            .unwrap();
+
+        // the first page is kept
+        let ids: Vec<_> = cancelled
+            .items
+            .iter()
+            .map(|problem| problem.api_id)
+            .collect();
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(cancelled.cancelled_at, Some(2));
+        assert!(path.exists());
+
+        let problems = api.all_problems_resumable(&checkpoint).await.unwrap();
+        let mut ids: Vec<_> = problems.iter().map(|problem| problem.api_id).collect();
+        ids.sort();
+
+        assert_eq!(ids, [1, 2, 3, 4, 5]);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn detects_stalled_cursor() {
+        let api = api(backend().with_stalled_cursor(true));
+
+        expect_stalled(api.all_problems().await.map(|p| p.len()), "no new problems");
+    }
+
+    #[tokio::test]
+    async fn stops_after_max_pages() {
+        let api = api(backend()).with_download_limits(DownloadLimits {
+            max_pages: Some(2),
+            time_budget: None,
+        });
+
+        expect_stalled(
+            api.all_problems().await.map(|p| p.len()),
+            "maximum of 2 pages",
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_after_time_budget() {
+        let backend = backend().with_page_delay(Duration::from_millis(50));
+        let api = api(backend).with_download_limits(DownloadLimits {
+            max_pages: None,
+            time_budget: Some(Duration::from_millis(60)),
+        });
+
+        expect_stalled(api.all_problems().await.map(|p| p.len()), "time budget");
+    }
+
+    #[tokio::test]
+    async fn errors_of_a_page_are_returned() {
+        let transport = FakeTransport::new();
+        transport.respond(
+            HttpMethod::Post,
+            "http://mock/Problems/GetRepeats",
+            200,
+            r#"{"AggregateResults": null, "Data": [], "Errors": {"": {"errors": ["oops"]}, "Id": {"errors": ["unknown problem"]}}, "Total": 0}"#,
+        );
+
+        let api = api(transport);
+
+        match api.problem_repeats(1).await {
+            Err(ApiError::Remote { url, errors }) => {
+                assert_eq!(url, "http://mock/Problems/GetRepeats");
+                assert_eq!(errors, ["oops", "Id: unknown problem"]);
+            }
+            other => panic!("expected the errors of the page, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn detects_empty_page_before_total() {
+        let transport = FakeTransport::new();
+        transport.respond(
+            HttpMethod::Post,
+            "http://mock/Problems/GetRepeats",
+            200,
+            r#"{"AggregateResults": null, "Data": [], "Errors": null, "Total": 3}"#,
+        );
+
+        let api = api(transport);
+        let repeats: Result<Vec<RepeatOrComment>> = api.problem_repeats(1).await;
+
+        expect_stalled(repeats.map(|r| r.len()), "empty page after 0 of 3");
+    }
+}
@@ -0,0 +1,117 @@
+use super::{TokenError, TransportError};
+
+use std::{fmt, io};
+
+// how much of a response body we keep around for error messages
+const EXCERPT_LEN: usize = 256;
+
+pub(crate) fn excerpt(body: &str) -> String {
+    match body.char_indices().nth(EXCERPT_LEN) {
+        Some((end, _)) => format!("{}...", &body[..end]),
+        None => body.to_owned(),
+    }
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    /// Logging in or refreshing the token was rejected.
+    Auth(TokenError),
+    /// The request could not be sent or the response could not be read.
+    Transport {
+        url: String,
+        source: TransportError,
+    },
+    /// The server answered with a non success status code.
+    Status {
+        url: String,
+        status: u16,
+        body: String,
+    },
+    /// The response did not match our model of it, most likely the api changed.
+    Decode {
+        url: String,
+        source: serde_json::Error,
+        payload: String,
+    },
+    /// The response had fields we don't know about or data where we only ever got null,
+    /// see `SchemaMode`. `drift` lists the locations, like `$[].holdsets[].locations`.
+    SchemaDrift {
+        url: String,
+        drift: Vec<String>,
+    },
+    /// The server answered the request with a list of errors instead of data.
+    Remote {
+        url: String,
+        errors: Vec<String>,
+    },
+    /// The server answered, but not in a way the protocol allows.
+    Protocol(String),
+    InvalidArgument(String),
+    /// A paginated download stopped making progress, `cursor` is the problem id or
+    /// page number we were at.
+    Stalled {
+        url: String,
+        cursor: i32,
+        reason: String,
+    },
+    /// Reading or writing the download checkpoint failed.
+    Checkpoint(io::Error),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::Auth(e) => write!(f, "authentication failed: {}", e),
+            ApiError::Transport { url, source } => {
+                write!(f, "request to {} failed: {}", url, source)
+            }
+            ApiError::Status { url, status, body } => {
+                write!(f, "{} returned status {}: {}", url, status, body)
+            }
+            ApiError::Decode {
+                url,
+                source,
+                payload,
+            } => write!(
+                f,
+                "could not decode response of {}: {}, payload: {}",
+                url, source, payload
+            ),
+            ApiError::SchemaDrift { url, drift } => write!(
+                f,
+                "response of {} does not match our models at {}",
+                url,
+                drift.join(", ")
+            ),
+            ApiError::Remote { url, errors } => {
+                write!(f, "{} reported errors: {}", url, errors.join(", "))
+            }
+            ApiError::Protocol(msg) => write!(f, "protocol violation: {}", msg),
+            ApiError::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            ApiError::Stalled {
+                url,
+                cursor,
+                reason,
+            } => write!(f, "download of {} stalled at {}: {}", url, cursor, reason),
+            ApiError::Checkpoint(e) => write!(f, "could not access download checkpoint: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ApiError::Auth(e) => Some(e),
+            ApiError::Transport { source, .. } => Some(source.as_ref()),
+            ApiError::Decode { source, .. } => Some(source),
+            ApiError::Checkpoint(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<TokenError> for ApiError {
+    fn from(e: TokenError) -> ApiError {
+        ApiError::Auth(e)
+    }
+}
@@ -0,0 +1,285 @@
+use super::{
+    de_rgb8_from_string, ser_rgb8_to_string, ApiError, HoldCoordinate, HoldDirection, HoldId,
+    HoldNumber, HoldRotation, HoldSetID, HoldSetup, HoldSetupID, MoonBoardConfiguration, Problem,
+    ProblemID, Result,
+};
+use log::warn;
+use rgb::RGB8;
+use serde::{Deserialize, Serialize};
+
+/// A hold mounted on the board, with everything needed to draw it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BoardHold {
+    pub id: HoldId,
+    pub number: HoldNumber,
+    /// The grid coordinate, moves of problems refer to holds with it.
+    pub coordinate: HoldCoordinate,
+    /// Position of the hold on the board image.
+    pub x: f64,
+    pub y: f64,
+    /// In degrees.
+    pub rotation: HoldRotation,
+    pub direction: HoldDirection,
+    /// The direction the hold pulls best in, like `N` or `SE`.
+    pub direction_string: String,
+    pub holdset_id: HoldSetID,
+    /// The id problems refer to the holdset with.
+    pub holdset_api_id: Option<HoldSetID>,
+    pub holdset: String,
+    /// The color of the location if it has one, the color of the holdset otherwise.
+    #[serde(
+        deserialize_with = "de_rgb8_from_string",
+        serialize_with = "ser_rgb8_to_string"
+    )]
+    pub color: RGB8,
+}
+
+/// The holds of a hold setup, flattened out of its holdsets. Holds without a valid
+/// coordinate are left out, no move can refer to them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BoardGeometry {
+    pub hold_setup_id: HoldSetupID,
+    /// The id problems refer to the setup with.
+    pub hold_setup_api_id: Option<HoldSetupID>,
+    pub description: String,
+    pub configurations: Vec<MoonBoardConfiguration>,
+    pub holds: Vec<BoardHold>,
+}
+
+impl BoardGeometry {
+    pub(crate) fn from_hold_setup(setup: &HoldSetup) -> BoardGeometry {
+        let holds = setup
+            .holdsets
+            .iter()
+            .flat_map(|holdset| {
+                holdset.holds.iter().filter_map(move |hold| {
+                    let coordinate = match hold.location.description.parse() {
+                        Ok(coordinate) => coordinate,
+                        Err(e) => {
+                            warn!(
+                                "leaving out hold {} of holdset {}: {}",
+                                hold.id, holdset.id, e
+                            );
+                            return None;
+                        }
+                    };
+
+                    Some(BoardHold {
+                        id: hold.id,
+                        number: hold.number.clone(),
+                        coordinate,
+                        x: hold.location.x,
+                        y: hold.location.y,
+                        rotation: hold.location.rotation,
+                        direction: hold.location.direction,
+                        direction_string: hold.location.direction_string.clone(),
+                        holdset_id: holdset.id,
+                        holdset_api_id: holdset.api_id,
+                        holdset: holdset.description.clone(),
+                        color: hold.location.color.unwrap_or(holdset.color),
+                    })
+                })
+            })
+            .collect();
+
+        BoardGeometry {
+            hold_setup_id: setup.id,
+            hold_setup_api_id: setup.api_id,
+            description: setup.description.clone(),
+            configurations: setup.moon_board_configurations.clone(),
+            holds,
+        }
+    }
+
+    /// The holds at `coordinate`, one for every holdset with a hold there.
+    pub fn holds_at(&self, coordinate: HoldCoordinate) -> impl Iterator<Item = &BoardHold> {
+        self.holds
+            .iter()
+            .filter(move |hold| hold.coordinate == coordinate)
+    }
+
+    /// The holds the moves of `problem` use, limited to the holdsets of the problem.
+    ///
+    /// `problem` has to be set on this setup.
+    pub fn resolve(&self, problem: &Problem) -> ResolvedProblem {
+        let holdsets = problem
+            .holdsets
+            .iter()
+            .map(|holdset| holdset.api_id)
+            .collect::<Vec<_>>();
+
+        let moves = problem
+            .moves
+            .iter()
+            .map(|mv| {
+                // the holdsets of a setup don't share positions, so there is at most one
+                let hold = self
+                    .holds_at(mv.description)
+                    .find(|hold| match hold.holdset_api_id {
+                        Some(id) => holdsets.contains(&id),
+                        None => false,
+                    })
+                    .cloned();
+
+                ResolvedMove {
+                    coordinate: mv.description,
+                    is_start: mv.is_start,
+                    is_end: mv.is_end,
+                    hold,
+                }
+            })
+            .collect();
+
+        ResolvedProblem {
+            problem_id: problem.api_id,
+            hold_setup_api_id: problem.holdsetup.api_id,
+            moves,
+        }
+    }
+}
+
+/// A move of a problem with the hold it uses.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ResolvedMove {
+    pub coordinate: HoldCoordinate,
+    pub is_start: bool,
+    pub is_end: bool,
+    /// None if none of the holdsets of the problem has a hold at `coordinate`.
+    pub hold: Option<BoardHold>,
+}
+
+/// The moves of a problem joined with the holds of its hold setup.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResolvedProblem {
+    pub problem_id: ProblemID,
+    pub hold_setup_api_id: HoldSetupID,
+    pub moves: Vec<ResolvedMove>,
+}
+
+impl ResolvedProblem {
+    /// The moves that reference a position without a hold.
+    pub fn missing(&self) -> impl Iterator<Item = &ResolvedMove> {
+        self.moves.iter().filter(|mv| mv.hold.is_none())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.missing().next().is_none()
+    }
+}
+
+impl Problem {
+    fn hold_setup<'a>(&self, setups: &'a [HoldSetup]) -> Result<&'a HoldSetup> {
+        setups
+            .iter()
+            .find(|setup| setup.api_id == Some(self.holdsetup.api_id))
+            .ok_or_else(|| {
+                ApiError::InvalidArgument(format!(
+                    "hold setup {} of problem {} is not in the given setups",
+                    self.holdsetup.api_id, self.api_id
+                ))
+            })
+    }
+
+    /// Finds the hold setup of this problem in `setups` and resolves the moves to its holds.
+    pub fn resolve_holds(&self, setups: &[HoldSetup]) -> Result<ResolvedProblem> {
+        Ok(self.hold_setup(setups)?.geometry().resolve(self))
+    }
+
+    /// Checks the configuration of this problem belongs to its hold setup in `setups` and
+    /// all moves are on the board of that setup.
+    ///
+    /// Moves are only checked to be valid coordinates when they are decoded, the size of
+    /// the board comes from the hold setups.
+    pub fn validate_moves(&self, setups: &[HoldSetup]) -> Result<()> {
+        let setup = self.hold_setup(setups)?;
+
+        if !setup
+            .moon_board_configurations
+            .iter()
+            .any(|configuration| configuration.id == self.moon_board_configuration_id)
+        {
+            return Err(ApiError::Protocol(format!(
+                "problem {} is for configuration {}, which hold setup {} does not have",
+                self.api_id, self.moon_board_configuration_id, self.holdsetup.api_id
+            )));
+        }
+
+        let size = setup.board_size().ok_or_else(|| {
+            ApiError::Protocol(format!("hold setup {} has no holds", self.holdsetup.api_id))
+        })?;
+
+        match self.moves.iter().find(|m| !size.contains(m.description)) {
+            Some(m) => Err(ApiError::Protocol(format!(
+                "move {} of problem {} is not on a board of {} columns and {} rows",
+                m.description, self.api_id, size.columns, size.rows
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moonboard_api::{BoardSize, Fixtures};
+
+    fn setup_and_problem() -> (HoldSetup, Problem) {
+        let fixtures = Fixtures::generate(1);
+        let setup = serde_json::from_value(fixtures.holdsetups[0].clone()).unwrap();
+        let problem = serde_json::from_value(fixtures.problems[0].clone()).unwrap();
+
+        (setup, problem)
+    }
+
+    #[test]
+    fn board_size_spans_all_holds() {
+        let (mut setup, _) = setup_and_problem();
+        assert_eq!(setup.board_size(), Some(BoardSize::MOONBOARD));
+
+        setup.holdsets[0].holds.pop();
+        assert_eq!(
+            setup.board_size(),
+            Some(BoardSize {
+                columns: 1,
+                rows: 5
+            })
+        );
+
+        setup.holdsets.clear();
+        assert_eq!(setup.board_size(), None);
+    }
+
+    #[test]
+    fn finds_holds_by_coordinate() {
+        let (mut setup, _) = setup_and_problem();
+        setup.holdsets[0].holds[1].location.description = "K 18".to_owned();
+
+        let geometry = setup.geometry();
+        let ids = |coordinate: &str| {
+            geometry
+                .holds_at(coordinate.parse().unwrap())
+                .map(|hold| hold.id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(geometry.holds.len(), 1);
+        assert_eq!(ids("A5"), [1]);
+        assert!(ids("K18").is_empty());
+    }
+
+    #[test]
+    fn validates_moves_against_the_board() {
+        let (setup, mut problem) = setup_and_problem();
+        problem.validate_moves(&[setup.clone()]).unwrap();
+
+        problem.moves[0].description = "L1".parse().unwrap();
+        match problem.validate_moves(&[setup.clone()]) {
+            Err(ApiError::Protocol(msg)) => assert!(msg.contains("move L1"), "{}", msg),
+            other => panic!("expected a protocol error, got {:?}", other),
+        }
+
+        problem.moon_board_configuration_id = 2;
+        assert!(problem.validate_moves(&[setup]).is_err());
+        assert!(problem.validate_moves(&[]).is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use std::{fmt, str::FromStr};
+
+// font grades from easiest to hardest with the v grade they are usually converted to
+const GRADES: &[(&str, u8)] = &[
+    ("4", 0),
+    ("4+", 0),
+    ("5", 1),
+    ("5+", 2),
+    ("6A", 3),
+    ("6A+", 3),
+    ("6B", 4),
+    ("6B+", 4),
+    ("6C", 5),
+    ("6C+", 5),
+    ("7A", 6),
+    ("7A+", 7),
+    ("7B", 8),
+    ("7B+", 8),
+    ("7C", 9),
+    ("7C+", 10),
+    ("8A", 11),
+    ("8A+", 12),
+    ("8B", 13),
+    ("8B+", 14),
+    ("8C", 15),
+    ("8C+", 16),
+    ("9A", 17),
+];
+
+/// A boulder grade on the Font scale, which is what the api uses.
+///
+/// Ordered from easy to hard. Displays as the api writes it, like `6B+`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Grade(u8);
+
+impl Grade {
+    /// The easiest grade, `4`.
+    pub const MIN: Grade = Grade(0);
+    /// The hardest grade, `9A`.
+    pub const MAX: Grade = Grade(GRADES.len() as u8 - 1);
+
+    pub fn font(&self) -> &'static str {
+        GRADES[self.0 as usize].0
+    }
+
+    /// The usual conversion to the V scale, several font grades share a v grade.
+    pub fn to_v(&self) -> VGrade {
+        VGrade(GRADES[self.0 as usize].1)
+    }
+
+    /// The easiest font grade that converts to `v`.
+    pub fn from_v(v: VGrade) -> Option<Grade> {
+        GRADES
+            .iter()
+            .position(|(_, grade)| *grade == v.0)
+            .map(|i| Grade(i as u8))
+    }
+
+    /// The grade `steps` grades harder, or easier for negative `steps`.
+    pub fn offset(&self, steps: i32) -> Option<Grade> {
+        let i = self.0 as i32 + steps;
+
+        if i >= 0 && i < GRADES.len() as i32 {
+            Some(Grade(i as u8))
+        } else {
+            None
+        }
+    }
+
+    /// One grade harder, so `6B` becomes `6B+`.
+    pub fn harder(&self) -> Option<Grade> {
+        self.offset(1)
+    }
+
+    pub fn easier(&self) -> Option<Grade> {
+        self.offset(-1)
+    }
+
+    /// All grades from `self` up to and including `to`.
+    pub fn up_to(self, to: Grade) -> impl Iterator<Item = Grade> {
+        (self.0..=to.0).map(Grade)
+    }
+}
+
+impl fmt::Display for Grade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.font())
+    }
+}
+
+/// Parses a font grade like `6B+`, the letter may be lowercase.
+impl FromStr for Grade {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Grade, String> {
+        let upper = s.trim().to_ascii_uppercase();
+
+        GRADES
+            .iter()
+            .position(|(font, _)| *font == upper)
+            .map(|i| Grade(i as u8))
+            .ok_or_else(|| format!("unknown grade {:?}", s))
+    }
+}
+
+impl Serialize for Grade {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.font())
+    }
+}
+
+impl<'de> Deserialize<'de> for Grade {
+    fn deserialize<D>(deserializer: D) -> Result<Grade, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// A grade on the V scale, like `V5`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VGrade(pub u8);
+
+impl fmt::Display for VGrade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "V{}", self.0)
+    }
+}
+
+impl FromStr for VGrade {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<VGrade, String> {
+        let s = s.trim();
+
+        let number = match s.get(..1) {
+            Some("V") | Some("v") => &s[1..],
+            _ => return Err(format!("invalid v grade {:?}", s)),
+        };
+
+        // `parse` would also take a leading `+`
+        if !number.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("invalid v grade {:?}", s));
+        }
+
+        number
+            .parse()
+            .map(VGrade)
+            .map_err(|_| format!("invalid v grade {:?}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grade(s: &str) -> Grade {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_and_displays_font_grades() {
+        assert_eq!(grade("6b+").to_string(), "6B+");
+        assert_eq!(grade(" 7A ").to_string(), "7A");
+        assert_eq!(Grade::MIN, grade("4"));
+        assert_eq!(Grade::MAX, grade("9A"));
+        assert!("6D".parse::<Grade>().is_err());
+        assert!("V5".parse::<Grade>().is_err());
+    }
+
+    #[test]
+    fn orders_from_easy_to_hard() {
+        assert!(grade("6A") < grade("6A+"));
+        assert!(grade("6C+") < grade("7A"));
+        assert!(grade("8B+") > grade("5+"));
+    }
+
+    #[test]
+    fn steps_through_grades() {
+        assert_eq!(grade("6B").harder(), Some(grade("6B+")));
+        assert_eq!(grade("7A").easier(), Some(grade("6C+")));
+        assert_eq!(grade("6A").offset(4), Some(grade("6C")));
+        assert_eq!(grade("6A").offset(-3), Some(grade("4+")));
+        assert_eq!(Grade::MAX.harder(), None);
+        assert_eq!(Grade::MIN.easier(), None);
+        assert_eq!(Grade::MIN.offset(-1), None);
+    }
+
+    #[test]
+    fn lists_grades_up_to() {
+        let grades: Vec<_> = grade("6C")
+            .up_to(grade("7A"))
+            .map(|g| g.to_string())
+            .collect();
+        assert_eq!(grades, ["6C", "6C+", "7A"]);
+
+        assert_eq!(grade("7A").up_to(grade("6C")).count(), 0);
+    }
+
+    #[test]
+    fn converts_to_v_grades() {
+        assert_eq!(grade("6A+").to_v(), VGrade(3));
+        assert_eq!(grade("7A").to_v(), VGrade(6));
+        assert_eq!(grade("8A").to_v().to_string(), "V11");
+
+        // the easiest font grade of a shared v grade
+        assert_eq!(Grade::from_v(VGrade(4)), Some(grade("6B")));
+        assert_eq!(Grade::from_v(VGrade(18)), None);
+
+        assert_eq!("v5".parse::<VGrade>(), Ok(VGrade(5)));
+        assert!("V+5".parse::<VGrade>().is_err());
+        assert!("5".parse::<VGrade>().is_err());
+    }
+}
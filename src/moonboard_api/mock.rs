@@ -0,0 +1,694 @@
+use super::{
+    transport::split_url, HttpBody, HttpRequest, HttpResponse, ProblemID, Transport, TransportError,
+};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use epochs;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs, io,
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+    time::Duration,
+};
+
+const MOCK_USER_ID: &str = "2b7a5c1e-8d4f-4e1a-9c3b-7f6e5d4c3b2a";
+
+/// The endpoints a `MockBackend` serves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MockEndpoint {
+    Token,
+    HoldSetups,
+    Problems,
+    UserSearch,
+    Comments,
+    Repeats,
+}
+
+impl FromStr for MockEndpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MockEndpoint, String> {
+        serde_json::from_value(Value::String(s.to_owned()))
+            .map_err(|_| format!("unknown endpoint {}", s))
+    }
+}
+
+/// Changes to the json a `MockBackend` sends, to simulate the api changing under us.
+///
+/// For list endpoints the change is applied to every element.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SchemaChange {
+    /// Adds `field`, or replaces its value if it already exists.
+    SetField {
+        endpoint: MockEndpoint,
+        field: String,
+        value: Value,
+    },
+    RemoveField {
+        endpoint: MockEndpoint,
+        field: String,
+    },
+}
+
+impl SchemaChange {
+    fn apply(&self, endpoint: MockEndpoint, items: &mut [Value]) {
+        for item in items.iter_mut().filter_map(Value::as_object_mut) {
+            match self {
+                SchemaChange::SetField {
+                    endpoint: target,
+                    field,
+                    value,
+                } if *target == endpoint => {
+                    item.insert(field.clone(), value.clone());
+                }
+                SchemaChange::RemoveField {
+                    endpoint: target,
+                    field,
+                } if *target == endpoint => {
+                    item.remove(field);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The data served by a `MockBackend`.
+///
+/// Kept as raw json, so fixtures can contain things our models don't expect.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fixtures {
+    pub holdsetups: Vec<Value>,
+    pub problems: Vec<Value>,
+    pub users: Vec<Value>,
+    /// The comments of each problem.
+    pub comments: HashMap<ProblemID, Vec<Value>>,
+    /// The repeats of each problem.
+    pub repeats: HashMap<ProblemID, Vec<Value>>,
+}
+
+impl Fixtures {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Fixtures> {
+        let fixtures = fs::read_to_string(path)?;
+        serde_json::from_str(&fixtures).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let fixtures = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, fixtures)
+    }
+
+    /// Made up data with one holdsetup, two users and `problems` problems, each of them
+    /// with one comment and one repeat.
+    pub fn generate(problems: usize) -> Fixtures {
+        let mut fixtures = Fixtures {
+            holdsetups: vec![generate_holdsetup()],
+            users: vec![
+                generate_user("7c1d2e3f-4a5b-4c6d-8e7f-9a0b1c2d3e4f", "Alex", "Honnold"),
+                generate_user("1f2e3d4c-5b6a-4978-8695-a4b3c2d1e0f9", "Janja", "Garnbret"),
+            ],
+            ..Default::default()
+        };
+
+        for id in 1..=problems as ProblemID {
+            fixtures.problems.push(generate_problem(id));
+            fixtures
+                .comments
+                .insert(id, vec![generate_repeat(id, true)]);
+            fixtures
+                .repeats
+                .insert(id, vec![generate_repeat(id, false)]);
+        }
+
+        fixtures
+    }
+}
+
+fn generate_holdsetup() -> Value {
+    json!({
+        "id": 1,
+        "isLocked": false,
+        "setby": null,
+        "apiId": 15,
+        "description": "MoonBoard Masters 2017",
+        "holdsets": [{
+            "id": 1,
+            "color": "#ffffff",
+            "apiId": 1,
+            "description": "Original School Holds",
+            "holds": [{
+                "holdType": 0,
+                "holdsetDescription": null,
+                "id": 1,
+                "location": {
+                    "color": null,
+                    "description": "A5",
+                    "direction": 0,
+                    "directionString": "N",
+                    "holdNumber": "1",
+                    "id": 1,
+                    "rotation": 0,
+                    "type": 0,
+                    "x": 0.0,
+                    "y": 4.0,
+                    "holdset": null
+                },
+                "number": "1"
+            }, {
+                "holdType": 0,
+                "holdsetDescription": null,
+                "id": 2,
+                "location": {
+                    "color": null,
+                    "description": "K18",
+                    "direction": 0,
+                    "directionString": "N",
+                    "holdNumber": "2",
+                    "id": 2,
+                    "rotation": 0,
+                    "type": 0,
+                    "x": 10.0,
+                    "y": 17.0,
+                    "holdset": null
+                },
+                "number": "2"
+            }]
+        }],
+        "active": true,
+        "allowClimbMethods": true,
+        "dateDeleted": null,
+        "dateInserted": "2017-01-01T00:00:00",
+        "dateUpdated": "2017-01-01T00:00:00",
+        "holdLayoutId": 1,
+        "moonBoardConfigurations": [{
+            "description": "40° MoonBoard",
+            "highGrade": "8B+",
+            "lowGrade": "6A+",
+            "id": 1
+        }]
+    })
+}
+
+fn generate_user(id: &str, firstname: &str, lastname: &str) -> Value {
+    json!({
+        "actionByMoonId": null,
+        "dateDeleted": null,
+        "dateInserted": "2019-06-01T12:00:00",
+        "dateUpdated": null,
+        "firstname": firstname,
+        "id": id,
+        "lastname": lastname,
+        "nickname": format!("{}{}", firstname, lastname),
+        "status": 0
+    })
+}
+
+fn generate_problem(id: ProblemID) -> Value {
+    json!({
+        "apiId": id,
+        "dateDeleted": null,
+        "dateInserted": "2020-01-01T00:00:00",
+        "dateUpdated": null,
+        "downgraded": false,
+        "grade": "6B+",
+        "hasBetaVideo": false,
+        "holdsets": [{ "apiId": 1, "description": "Original School Holds", "locations": null }],
+        "holdsetup": { "apiId": 15, "description": "MoonBoard Masters 2017", "holdsets": null },
+        "isBenchmark": id % 10 == 0,
+        "isMaster": false,
+        "method": "Feet follow hands",
+        "moonBoardConfigurationId": 1,
+        "moves": [
+            { "description": "A5", "isEnd": false, "isStart": true, "problemId": id },
+            { "description": "K18", "isEnd": true, "isStart": false, "problemId": id }
+        ],
+        "name": format!("MOCK PROBLEM {}", id),
+        "repeats": 1,
+        "setby": "AlexHonnold",
+        "setbyId": "7c1d2e3f-4a5b-4c6d-8e7f-9a0b1c2d3e4f",
+        "upgraded": false,
+        "userGrade": null,
+        "userRating": null
+    })
+}
+
+fn generate_repeat(problem_id: ProblemID, with_comment: bool) -> Value {
+    json!({
+        "Comment": if with_comment { Some("nice one") } else { None },
+        "Attempts": 1,
+        "DateClimbed": "/Date(1585699200000)/",
+        "DateClimbedAsString": "01 Apr 2020",
+        "DateInserted": "2020-04-01T00:00:00",
+        "Grade": "6B+",
+        "Id": problem_id * 2 + with_comment as ProblemID,
+        "IsSuggestedBenchmark": false,
+        "MoonBoard": null,
+        "NumberOfTries": "Flashed",
+        "Problem": null,
+        "Rating": 3,
+        "User": {
+            "CanShareData": true,
+            "City": null,
+            "Country": null,
+            "Firstname": "Janja",
+            "Id": "1f2e3d4c-5b6a-4978-8695-a4b3c2d1e0f9",
+            "Lastname": "Garnbret",
+            "Nickname": "JanjaGarnbret",
+            "ProfileImageUrl": ""
+        }
+    })
+}
+
+struct MockState {
+    fixtures: Fixtures,
+    access_tokens: HashMap<String, DateTime<Utc>>,
+    refresh_tokens: HashSet<String>,
+    issued_tokens: u64,
+    failures: HashMap<MockEndpoint, VecDeque<u16>>,
+}
+
+/// Serves fixture data like the MoonBoard servers do, to test logins, downloads and
+/// pagination without network or real credentials.
+///
+/// The paths are the ones of `Endpoints`, with both the api and the website served from the
+/// root of the url, so use the same url for both.
+pub struct MockBackend {
+    username: String,
+    password: String,
+    token_lifetime: Duration,
+    problem_page_size: usize,
+    page_delay: Duration,
+    stalled_cursor: bool,
+    schema_changes: Vec<SchemaChange>,
+    state: Mutex<MockState>,
+}
+
+fn response(status: u16, body: Value) -> HttpResponse {
+    HttpResponse {
+        status,
+        body: body.to_string(),
+    }
+}
+
+fn route(url: &str) -> Option<(MockEndpoint, Vec<String>)> {
+    let (path, _) = split_url(url);
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    let (endpoint, args) = match segments.as_slice() {
+        ["token"] => (MockEndpoint::Token, &[][..]),
+        ["v1", "_moonapi", "Holdsetup", args @ ..] => (MockEndpoint::HoldSetups, args),
+        ["v1", "_moonapi", "problems", "v2", args @ ..] => (MockEndpoint::Problems, args),
+        ["v1", "_moonapi", "Users", "Search"] => (MockEndpoint::UserSearch, &[][..]),
+        ["Problems", "GetComments"] => (MockEndpoint::Comments, &[][..]),
+        ["Problems", "GetRepeats"] => (MockEndpoint::Repeats, &[][..]),
+        _ => return None,
+    };
+
+    Some((endpoint, args.iter().map(|arg| arg.to_string()).collect()))
+}
+
+fn parse_form(body: &HttpBody) -> HashMap<String, String> {
+    match body {
+        HttpBody::Form(form) => serde_urlencoded::from_str(form).unwrap_or_default(),
+        _ => HashMap::new(),
+    }
+}
+
+fn parse_date(value: &Value) -> Option<NaiveDateTime> {
+    let s = value.as_str()?;
+
+    let fmt = if s.contains('.') {
+        "%Y-%m-%dT%H:%M:%S.%f"
+    } else {
+        "%Y-%m-%dT%H:%M:%S"
+    };
+
+    NaiveDateTime::parse_from_str(s, fmt).ok()
+}
+
+impl MockBackend {
+    pub fn new(mut fixtures: Fixtures) -> MockBackend {
+        // the problems are paged through by their id
+        fixtures
+            .problems
+            .sort_by_key(|problem| problem["apiId"].as_i64());
+
+        MockBackend {
+            username: "mock".to_owned(),
+            password: "mock".to_owned(),
+            token_lifetime: Duration::from_secs(1_209_599),
+            problem_page_size: 1000,
+            page_delay: Duration::from_secs(0),
+            stalled_cursor: false,
+            schema_changes: Vec::new(),
+            state: Mutex::new(MockState {
+                fixtures,
+                access_tokens: HashMap::new(),
+                refresh_tokens: HashSet::new(),
+                issued_tokens: 0,
+                failures: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn with_credentials(mut self, username: String, password: String) -> MockBackend {
+        self.username = username;
+        self.password = password;
+        self
+    }
+
+    pub fn with_token_lifetime(mut self, token_lifetime: Duration) -> MockBackend {
+        self.token_lifetime = token_lifetime;
+        self
+    }
+
+    pub fn with_problem_page_size(mut self, problem_page_size: usize) -> MockBackend {
+        self.problem_page_size = problem_page_size.max(1);
+        self
+    }
+
+    /// Delay every page of problems, comments and repeats by `page_delay`.
+    pub fn with_page_delay(mut self, page_delay: Duration) -> MockBackend {
+        self.page_delay = page_delay;
+        self
+    }
+
+    /// Ignore the cursor of problem downloads and always send the first page, like a
+    /// server whose pagination is broken.
+    pub fn with_stalled_cursor(mut self, stalled_cursor: bool) -> MockBackend {
+        self.stalled_cursor = stalled_cursor;
+        self
+    }
+
+    pub fn with_schema_change(mut self, change: SchemaChange) -> MockBackend {
+        self.schema_changes.push(change);
+        self
+    }
+
+    /// Answer the next `times` requests to `endpoint` with `status`.
+    pub fn fail_next(&self, endpoint: MockEndpoint, status: u16, times: usize) {
+        let mut state = self.state.lock().unwrap();
+        let failures = state.failures.entry(endpoint).or_default();
+
+        failures.extend(std::iter::repeat(status).take(times));
+    }
+
+    /// Reject all access tokens handed out so far, like a server side logout.
+    pub fn expire_tokens(&self) {
+        self.state.lock().unwrap().access_tokens.clear();
+    }
+
+    /// Reject all access and refresh tokens handed out so far, so only a new login helps.
+    pub fn revoke_tokens(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        state.access_tokens.clear();
+        state.refresh_tokens.clear();
+    }
+
+    pub fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        let (endpoint, args) = match route(&request.url) {
+            Some(route) => route,
+            None => return response(404, json!({ "Message": "No HTTP resource was found" })),
+        };
+
+        let mut state = self.state.lock().unwrap();
+
+        let failure = state
+            .failures
+            .get_mut(&endpoint)
+            .and_then(VecDeque::pop_front);
+
+        if let Some(status) = failure {
+            return response(status, json!({ "Message": "injected failure" }));
+        }
+
+        let needs_token = match endpoint {
+            MockEndpoint::HoldSetups | MockEndpoint::Problems | MockEndpoint::UserSearch => true,
+            _ => false,
+        };
+
+        if needs_token && !state.is_authorized(request.bearer.as_deref()) {
+            return response(
+                401,
+                json!({ "Message": "Authorization has been denied for this request." }),
+            );
+        }
+
+        match endpoint {
+            MockEndpoint::Token => self.token(&mut state, request),
+            MockEndpoint::HoldSetups => {
+                let holdsetups = self.changed(endpoint, state.fixtures.holdsetups.clone());
+                response(200, Value::Array(holdsetups))
+            }
+            MockEndpoint::Problems => self.problems(&state, &args),
+            MockEndpoint::UserSearch => self.user_search(&state, request),
+            MockEndpoint::Comments => {
+                let (_, query) = split_url(&request.url);
+                let query: HashMap<String, String> =
+                    serde_urlencoded::from_str(query).unwrap_or_default();
+                let comments = query
+                    .get("problemId")
+                    .and_then(|id| id.parse().ok())
+                    .and_then(|id| state.fixtures.comments.get(&id));
+
+                self.paged(endpoint, comments, request)
+            }
+            MockEndpoint::Repeats => {
+                let form = parse_form(&request.body);
+                let repeats = form
+                    .get("filter")
+                    .and_then(|filter| filter.trim_start_matches("Id~eq~").parse().ok())
+                    .and_then(|id| state.fixtures.repeats.get(&id));
+
+                self.paged(endpoint, repeats, request)
+            }
+        }
+    }
+
+    fn changed(&self, endpoint: MockEndpoint, mut items: Vec<Value>) -> Vec<Value> {
+        for change in &self.schema_changes {
+            change.apply(endpoint, &mut items);
+        }
+
+        items
+    }
+
+    fn token(&self, state: &mut MockState, request: &HttpRequest) -> HttpResponse {
+        let form = parse_form(&request.body);
+
+        let granted = match form.get("grant_type").map(String::as_str) {
+            Some("password") => {
+                form.get("username") == Some(&self.username)
+                    && form.get("password") == Some(&self.password)
+            }
+            // refresh tokens can only be used once
+            Some("refresh_token") => form.get("refresh_token").map_or(false, |refresh_token| {
+                state.refresh_tokens.remove(refresh_token)
+            }),
+            _ => false,
+        };
+
+        if !granted {
+            return response(
+                400,
+                json!({
+                    "error": "invalid_grant",
+                    "error_description": "The user name or password is incorrect."
+                }),
+            );
+        }
+
+        let token = state.issue_token(&self.username, self.token_lifetime);
+        let mut token = self.changed(MockEndpoint::Token, vec![token]);
+
+        response(200, token.remove(0))
+    }
+
+    fn problems(&self, state: &MockState, args: &[String]) -> HttpResponse {
+        let bad_request = |arg: &str| {
+            response(
+                400,
+                json!({ "Message": format!("The request is invalid: {}", arg) }),
+            )
+        };
+
+        let cursor: i64 = match args.first().map(|cursor| cursor.parse()) {
+            Some(Ok(_)) if self.stalled_cursor => 0,
+            Some(Ok(cursor)) => cursor,
+            _ => return bad_request("problem id"),
+        };
+
+        // the update download passes windows dates for inserted, updated and deleted
+        let mut since = Vec::new();
+
+        for arg in args.iter().skip(1) {
+            match arg.parse().ok().and_then(epochs::windows_date) {
+                Some(date) => since.push(date),
+                None => return bad_request(arg),
+            }
+        }
+
+        let fields = ["dateInserted", "dateUpdated", "dateDeleted"];
+
+        let remaining: Vec<&Value> = state
+            .fixtures
+            .problems
+            .iter()
+            .filter(|problem| problem["apiId"].as_i64().map_or(false, |id| id > cursor))
+            .filter(|problem| {
+                since.is_empty()
+                    || since.iter().zip(&fields).any(|(since, field)| {
+                        parse_date(&problem[*field]).map_or(false, |date| date > *since)
+                    })
+            })
+            .collect();
+
+        let page: Vec<Value> = remaining
+            .iter()
+            .take(self.problem_page_size)
+            .map(|&problem| problem.clone())
+            .collect();
+        let total = remaining.len() - page.len();
+
+        response(
+            200,
+            json!({ "total": total, "data": self.changed(MockEndpoint::Problems, page) }),
+        )
+    }
+
+    fn user_search(&self, state: &MockState, request: &HttpRequest) -> HttpResponse {
+        let query = match &request.body {
+            HttpBody::Json(json) => serde_json::from_str::<Value>(json)
+                .ok()
+                .and_then(|body| body["Query"].as_str().map(str::to_lowercase))
+                .unwrap_or_default(),
+            _ => String::new(),
+        };
+
+        let users = state
+            .fixtures
+            .users
+            .iter()
+            .filter(|user| {
+                ["nickname", "firstname", "lastname"].iter().any(|field| {
+                    user[*field]
+                        .as_str()
+                        .map_or(false, |name| name.to_lowercase().contains(&query))
+                })
+            })
+            .cloned()
+            .collect();
+
+        response(
+            200,
+            Value::Array(self.changed(MockEndpoint::UserSearch, users)),
+        )
+    }
+
+    fn paged(
+        &self,
+        endpoint: MockEndpoint,
+        items: Option<&Vec<Value>>,
+        request: &HttpRequest,
+    ) -> HttpResponse {
+        let form = parse_form(&request.body);
+        let number = |key: &str, default: usize| {
+            form.get(key)
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(default)
+                .max(1)
+        };
+
+        let page = number("page", 1);
+        let page_size = number("pageSize", 10);
+        let items = items.map_or(&[][..], |items| &items[..]);
+
+        let data = items
+            .iter()
+            .skip((page - 1) * page_size)
+            .take(page_size)
+            .cloned()
+            .collect();
+
+        response(
+            200,
+            json!({
+                "AggregateResults": null,
+                "Data": self.changed(endpoint, data),
+                "Errors": null,
+                "Total": items.len()
+            }),
+        )
+    }
+}
+
+impl MockState {
+    fn is_authorized(&self, bearer: Option<&str>) -> bool {
+        bearer
+            .and_then(|bearer| self.access_tokens.get(bearer))
+            .map_or(false, |expires| *expires > Utc::now())
+    }
+
+    fn issue_token(&mut self, username: &str, lifetime: Duration) -> Value {
+        self.issued_tokens += 1;
+
+        let access_token = format!("mock-access-token-{}", self.issued_tokens);
+        let refresh_token = format!("mock-refresh-token-{}", self.issued_tokens);
+        let issued = Utc::now();
+        let expires = issued
+            + chrono::Duration::from_std(lifetime).unwrap_or_else(|_| chrono::Duration::weeks(52));
+
+        self.access_tokens.insert(access_token.clone(), expires);
+        self.refresh_tokens.insert(refresh_token.clone());
+
+        json!({
+            ".expires": expires.to_rfc2822(),
+            ".issued": issued.to_rfc2822(),
+            "AgreeTerms": "True",
+            "Firstname": "Mock",
+            "Lastname": "User",
+            "IsCommercial": "False",
+            "Nickname": username,
+            "Role": "MoonBoard User",
+            "UserId": MOCK_USER_ID,
+            "access_token": access_token,
+            "as:client_id": "com.moonclimbing.mb",
+            "expires_in": lifetime.as_secs(),
+            "refresh_token": refresh_token,
+            "token_type": "bearer",
+            "userName": username
+        })
+    }
+}
+
+impl Transport for MockBackend {
+    fn send<'a>(
+        &'a self,
+        request: &'a HttpRequest,
+    ) -> BoxFuture<'a, Result<HttpResponse, TransportError>> {
+        Box::pin(async move {
+            let is_page = match route(&request.url) {
+                Some((MockEndpoint::Problems, _))
+                | Some((MockEndpoint::Comments, _))
+                | Some((MockEndpoint::Repeats, _)) => true,
+                _ => false,
+            };
+
+            if is_page && self.page_delay > Duration::from_secs(0) {
+                tokio::time::delay_for(self.page_delay).await;
+            }
+
+            Ok(self.handle(request))
+        })
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Decides which failed requests are retried and how long we wait in between.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of tries, including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// Fraction of the backoff that is randomized, so parallel requests don't retry in lockstep.
+    pub jitter: f64,
+    /// HTTP status codes that are considered transient.
+    pub retry_on: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            retry_on: vec![429, 500, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Fail on the first error, like a plain request would.
+    pub fn never() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Backoff before the retry following the `attempt`th try.
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(attempt as i32 - 1);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+        let jitter = 1.0 + self.jitter * (2.0 * rand::random::<f64>() - 1.0);
+
+        Duration::from_secs_f64((backoff * jitter).max(0.0))
+    }
+}
+
+/// Spaces out requests so they are at least `min_interval` apart.
+pub(crate) struct RateLimiter {
+    min_interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(min_interval: Duration) -> RateLimiter {
+        RateLimiter {
+            min_interval,
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub(crate) async fn wait(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.min_interval;
+
+            slot
+        };
+
+        tokio::time::delay_until(slot.into()).await;
+    }
+}
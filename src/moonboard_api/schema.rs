@@ -0,0 +1,593 @@
+use super::{
+    error::excerpt, AggregateResult, ApiError, Hold, HoldLocation, HoldSet, HoldSetFromProblem,
+    HoldSetup, HoldSetupFromProblem, MoonBoardConfiguration, MoonBoardFromRepeatOrComment, Move,
+    Paged, Problem, ProblemFromRepeatOrComment, Problems, RepeatOrComment, User,
+    UserFromRepeatOrComment,
+};
+
+use log::warn;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{Map, Value};
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// What to do when a response does not match our models.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaMode {
+    /// Unknown fields and data in fields we only ever got null for fail the request, so do
+    /// values of the wrong type.
+    Strict,
+    /// They are kept in the `extra` field of the model and logged as a warning, once per
+    /// location. A value of the wrong type is moved to `extra` if the field can be left
+    /// empty, otherwise the element of the list it is in is dropped.
+    Lenient,
+}
+
+impl Default for SchemaMode {
+    fn default() -> SchemaMode {
+        SchemaMode::Strict
+    }
+}
+
+/// Where a model deviates from the json we expect, by location, like
+/// `$[].holdsets[].locations`, with an example of what we got there.
+pub(crate) type DriftReport = BTreeMap<String, String>;
+
+/// The locations of the fields our models declare, like `$[].holdsets[].description`.
+pub(crate) type DeclaredFields = BTreeSet<String>;
+
+/// Walks a decoded model to find the places the api sent something we don't expect.
+///
+/// Every model has an `extra` field for those, with the fields we don't know about, data
+/// from placeholder fields and values of the wrong type. It is only ever filled in
+/// `SchemaMode::Lenient`, in strict mode the request fails instead.
+pub(crate) trait Drift {
+    /// Moves data out of placeholder fields into `extra` and records everything that is in
+    /// `extra` afterwards in `report`, `path` is the location of `self`.
+    fn drift(&mut self, path: &str, report: &mut DriftReport);
+
+    /// Keeps `value`, which did not fit at `location` below `self`, in `extra`.
+    fn keep(&mut self, _location: String, _value: Value) {}
+
+    /// Adds the locations of the fields `self` and the models in it declare to `fields`,
+    /// `path` is the location of `self`.
+    fn declared(&self, _path: &str, _fields: &mut DeclaredFields) {}
+}
+
+/// Decoding for `SchemaMode::Lenient`, which gets past values of the wrong type.
+pub(crate) trait Lenient: DeserializeOwned + Drift {
+    /// Decodes `value`, if it does not fit, the first value that makes it fit when left
+    /// out is moved to `extra`. `path` is the location of `value`, for reporting elements
+    /// that are dropped.
+    fn decode_lenient(
+        value: Value,
+        _path: &str,
+        _report: &mut DriftReport,
+    ) -> serde_json::Result<Self> {
+        repair(value)
+    }
+}
+
+// json pointers to every value below the root, children before their parents, so the
+// smallest part of the json is left out
+fn pointers(value: &Value, pointer: String, pointers: &mut Vec<String>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                let key = key.replace('~', "~0").replace('/', "~1");
+                self::pointers(value, format!("{}/{}", pointer, key), pointers);
+            }
+        }
+        Value::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                self::pointers(value, format!("{}/{}", pointer, i), pointers);
+            }
+        }
+        _ => {}
+    }
+
+    if !pointer.is_empty() {
+        pointers.push(pointer);
+    }
+}
+
+// `/moves/3/description` is reported as `moves[].description`
+fn location(pointer: &str) -> String {
+    let mut location = String::new();
+
+    for segment in pointer.split('/').skip(1) {
+        if segment.parse::<usize>().is_ok() {
+            location.push_str("[]");
+        } else {
+            if !location.is_empty() {
+                location.push('.');
+            }
+            location.push_str(&segment.replace("~1", "/").replace("~0", "~"));
+        }
+    }
+
+    location
+}
+
+fn repair<T: DeserializeOwned + Drift>(value: Value) -> serde_json::Result<T> {
+    let error = match T::deserialize(&value) {
+        Ok(model) => return Ok(model),
+        Err(e) => e,
+    };
+
+    let mut candidates = Vec::new();
+    pointers(&value, String::new(), &mut candidates);
+
+    for pointer in candidates {
+        let mut candidate = value.clone();
+        let original = match candidate.pointer_mut(&pointer) {
+            Some(Value::Null) | None => continue,
+            Some(field) => field.take(),
+        };
+
+        if let Ok(mut model) = T::deserialize(&candidate) {
+            model.keep(location(&pointer), original);
+            return Ok(model);
+        }
+    }
+
+    Err(error)
+}
+
+impl<T: Drift> Drift for Vec<T> {
+    fn drift(&mut self, path: &str, report: &mut DriftReport) {
+        let path = format!("{}[]", path);
+
+        for elem in self {
+            elem.drift(&path, report);
+        }
+    }
+
+    fn declared(&self, path: &str, fields: &mut DeclaredFields) {
+        let path = format!("{}[]", path);
+
+        for elem in self {
+            elem.declared(&path, fields);
+        }
+    }
+}
+
+impl<T: Drift> Drift for Box<T> {
+    fn drift(&mut self, path: &str, report: &mut DriftReport) {
+        (**self).drift(path, report);
+    }
+
+    fn declared(&self, path: &str, fields: &mut DeclaredFields) {
+        (**self).declared(path, fields);
+    }
+}
+
+// elements that still don't fit are dropped, instead of failing the whole response
+impl<T: Lenient> Lenient for Vec<T> {
+    fn decode_lenient(
+        value: Value,
+        path: &str,
+        report: &mut DriftReport,
+    ) -> serde_json::Result<Vec<T>> {
+        let values = match value {
+            Value::Array(values) => values,
+            value => return Vec::<T>::deserialize(value),
+        };
+
+        let path = format!("{}[]", path);
+        let mut elems = Vec::with_capacity(values.len());
+
+        for value in values {
+            match T::decode_lenient(value.clone(), &path, report) {
+                Ok(elem) => elems.push(elem),
+                Err(e) => {
+                    report.entry(path.clone()).or_insert_with(|| {
+                        format!("dropped {}: {}", excerpt(&value.to_string()), e)
+                    });
+                }
+            }
+        }
+
+        Ok(elems)
+    }
+}
+
+impl<T: Drift> Drift for Option<T> {
+    fn drift(&mut self, path: &str, report: &mut DriftReport) {
+        if let Some(inner) = self {
+            inner.drift(path, report);
+        }
+    }
+
+    fn declared(&self, path: &str, fields: &mut DeclaredFields) {
+        if let Some(inner) = self {
+            inner.declared(path, fields);
+        }
+    }
+}
+
+fn placeholder(key: &str, field: &mut Option<Value>, extra: &mut Map<String, Value>) {
+    if let Some(value) = field.take() {
+        extra.insert(key.to_owned(), value);
+    }
+}
+
+pub(crate) fn unknown_fields(path: &str, extra: &Map<String, Value>, report: &mut DriftReport) {
+    for (key, value) in extra {
+        report
+            .entry(format!("{}.{}", path, key))
+            .or_insert_with(|| excerpt(&value.to_string()));
+    }
+}
+
+fn declare(path: &str, keys: &[&str], fields: &mut DeclaredFields) {
+    for key in keys {
+        fields.insert(format!("{}.{}", path, key));
+    }
+}
+
+// `fields` are the keys of the other fields of the model
+macro_rules! drift {
+    ($ty: ty, fields: [$($plain_key: expr),*], placeholders: { $($field: ident => $key: expr),* }, nested: { $($nested: ident => $nested_key: expr),* }) => {
+        impl Drift for $ty {
+            fn drift(&mut self, path: &str, report: &mut DriftReport) {
+                $(placeholder($key, &mut self.$field, &mut self.extra);)*
+                unknown_fields(path, &self.extra, report);
+                $(self.$nested.drift(&format!("{}.{}", path, $nested_key), report);)*
+            }
+
+            fn keep(&mut self, location: String, value: Value) {
+                self.extra.insert(location, value);
+            }
+
+            fn declared(&self, path: &str, fields: &mut DeclaredFields) {
+                declare(path, &[$($plain_key,)* $($key,)* $($nested_key),*], fields);
+                $(self.$nested.declared(&format!("{}.{}", path, $nested_key), fields);)*
+            }
+        }
+
+        impl Lenient for $ty {}
+    };
+}
+
+drift!(
+    HoldSetFromProblem,
+    fields: ["apiId", "description"],
+    placeholders: {},
+    nested: { locations => "locations" }
+);
+drift!(
+    HoldSetupFromProblem,
+    fields: ["apiId", "description"],
+    placeholders: {},
+    nested: { holdsets => "holdsets" }
+);
+drift!(
+    HoldLocation,
+    fields: [
+        "color", "description", "direction", "directionString", "holdNumber", "id",
+        "rotation", "type", "x", "y"
+    ],
+    placeholders: {},
+    nested: { holdset => "holdset" }
+);
+drift!(
+    Hold,
+    fields: ["holdType", "holdsetDescription", "id", "number"],
+    placeholders: {},
+    nested: { location => "location" }
+);
+drift!(
+    HoldSet,
+    fields: ["id", "color", "apiId", "description"],
+    placeholders: {},
+    nested: { holds => "holds" }
+);
+drift!(
+    MoonBoardConfiguration,
+    fields: ["description", "highGrade", "lowGrade", "id"],
+    placeholders: {},
+    nested: {}
+);
+drift!(
+    HoldSetup,
+    fields: [
+        "id", "isLocked", "setby", "apiId", "description", "active", "allowClimbMethods",
+        "dateDeleted", "dateInserted", "dateUpdated", "holdLayoutId"
+    ],
+    placeholders: {},
+    nested: { holdsets => "holdsets", moon_board_configurations => "moonBoardConfigurations" }
+);
+drift!(
+    Move,
+    fields: ["description", "isEnd", "isStart", "problemId"],
+    placeholders: {},
+    nested: {}
+);
+drift!(
+    Problem,
+    fields: [
+        "apiId", "dateDeleted", "dateInserted", "dateUpdated", "downgraded", "grade",
+        "hasBetaVideo", "isBenchmark", "isMaster", "method", "moonBoardConfigurationId",
+        "name", "repeats", "setby", "setbyId", "upgraded", "userGrade", "userRating"
+    ],
+    placeholders: {},
+    nested: { holdsets => "holdsets", holdsetup => "holdsetup", moves => "moves" }
+);
+drift!(
+    User,
+    fields: [
+        "dateDeleted", "dateInserted", "dateUpdated", "firstname", "id", "lastname",
+        "nickname", "status"
+    ],
+    placeholders: { action_by_moon_id => "actionByMoonId" },
+    nested: {}
+);
+drift!(
+    RepeatOrComment,
+    fields: [
+        "Comment", "Attempts", "DateClimbed", "DateClimbedAsString", "DateInserted", "Grade",
+        "Id", "IsSuggestedBenchmark", "NumberOfTries", "Rating"
+    ],
+    placeholders: {},
+    nested: { moon_board => "MoonBoard", problem => "Problem", user => "User" }
+);
+drift!(
+    UserFromRepeatOrComment,
+    fields: [
+        "CanShareData", "City", "Country", "Firstname", "Id", "Lastname", "Nickname",
+        "ProfileImageUrl"
+    ],
+    placeholders: {},
+    nested: {}
+);
+drift!(MoonBoardFromRepeatOrComment, fields: ["Id", "Description"], placeholders: {}, nested: {});
+drift!(
+    ProblemFromRepeatOrComment,
+    fields: ["Id", "Name", "Grade"],
+    placeholders: {},
+    nested: {}
+);
+drift!(
+    AggregateResult,
+    fields: [
+        "Value", "Member", "FormattedValue", "ItemCount", "Caption", "FunctionName",
+        "AggregateMethodName"
+    ],
+    placeholders: {},
+    nested: {}
+);
+
+impl<T: Drift> Drift for Paged<T> {
+    fn drift(&mut self, path: &str, report: &mut DriftReport) {
+        unknown_fields(path, &self.extra, report);
+        self.aggregate_results
+            .drift(&format!("{}.AggregateResults", path), report);
+        self.data.drift(&format!("{}.Data", path), report);
+    }
+
+    fn keep(&mut self, location: String, value: Value) {
+        self.extra.insert(location, value);
+    }
+
+    fn declared(&self, path: &str, fields: &mut DeclaredFields) {
+        declare(
+            path,
+            &["AggregateResults", "Data", "Errors", "Total"],
+            fields,
+        );
+        self.aggregate_results
+            .declared(&format!("{}.AggregateResults", path), fields);
+        self.data.declared(&format!("{}.Data", path), fields);
+    }
+}
+
+impl Drift for Problems {
+    fn drift(&mut self, path: &str, report: &mut DriftReport) {
+        unknown_fields(path, &self.extra, report);
+        self.data.drift(&format!("{}.data", path), report);
+    }
+
+    fn keep(&mut self, location: String, value: Value) {
+        self.extra.insert(location, value);
+    }
+
+    fn declared(&self, path: &str, fields: &mut DeclaredFields) {
+        declare(path, &["total", "data"], fields);
+        self.data.declared(&format!("{}.data", path), fields);
+    }
+}
+
+impl Lenient for Problems {
+    fn decode_lenient(
+        mut value: Value,
+        path: &str,
+        report: &mut DriftReport,
+    ) -> serde_json::Result<Problems> {
+        let data = match value.get_mut("data") {
+            Some(data) => std::mem::replace(data, Value::Array(Vec::new())),
+            None => return repair(value),
+        };
+
+        let mut problems: Problems = repair(value)?;
+        problems.data = Vec::decode_lenient(data, &format!("{}.data", path), report)?;
+
+        Ok(problems)
+    }
+}
+
+// the elements are decoded on their own, so one that doesn't fit only drops itself
+impl<T: Lenient> Lenient for Paged<T> {
+    fn decode_lenient(
+        mut value: Value,
+        path: &str,
+        report: &mut DriftReport,
+    ) -> serde_json::Result<Paged<T>> {
+        let data = match value.get_mut("Data") {
+            Some(data) => std::mem::replace(data, Value::Array(Vec::new())),
+            None => return repair(value),
+        };
+
+        let mut page: Paged<T> = repair(value)?;
+        page.data = Vec::<T>::decode_lenient(data, &format!("{}.Data", path), report)?;
+
+        Ok(page)
+    }
+}
+
+/// Decodes `value` and reports its drift according to `mode`, `reported` holds the
+/// locations already warned about.
+pub(crate) fn decode<T: Lenient>(
+    mode: SchemaMode,
+    reported: &mut HashSet<String>,
+    url: &str,
+    value: Value,
+) -> Result<T, ApiError> {
+    let mut report = DriftReport::new();
+
+    let decoded = match mode {
+        SchemaMode::Strict => T::deserialize(&value),
+        SchemaMode::Lenient => T::decode_lenient(value.clone(), "$", &mut report),
+    };
+
+    let mut model = decoded.map_err(|source| ApiError::Decode {
+        url: url.to_owned(),
+        source,
+        payload: excerpt(&value.to_string()),
+    })?;
+
+    model.drift("$", &mut report);
+    check(mode, reported, url, report)?;
+
+    Ok(model)
+}
+
+/// Decodes `value` like `SchemaMode::Lenient` does and returns all drift found on the way,
+/// with the locations of the fields the decoded models declare.
+pub(crate) fn drift_of<T: Lenient>(
+    value: Value,
+) -> serde_json::Result<(DriftReport, DeclaredFields)> {
+    let mut report = DriftReport::new();
+    let mut model = T::decode_lenient(value, "$", &mut report)?;
+    model.drift("$", &mut report);
+
+    let mut fields = DeclaredFields::new();
+    model.declared("$", &mut fields);
+
+    Ok((report, fields))
+}
+
+fn check(
+    mode: SchemaMode,
+    reported: &mut HashSet<String>,
+    url: &str,
+    report: DriftReport,
+) -> Result<(), ApiError> {
+    if report.is_empty() {
+        return Ok(());
+    }
+
+    match mode {
+        SchemaMode::Strict => Err(ApiError::SchemaDrift {
+            url: url.to_owned(),
+            drift: report.into_iter().map(|(location, _)| location).collect(),
+        }),
+        SchemaMode::Lenient => {
+            for (location, example) in report {
+                if reported.insert(location.clone()) {
+                    warn!("{} sent unexpected data at {}: {}", url, location, example);
+                }
+            }
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moonboard_api::Fixtures;
+
+    fn problems(count: usize) -> Vec<Value> {
+        Fixtures::generate(count).problems
+    }
+
+    #[test]
+    fn strict_mode_rejects_wrong_types() {
+        let mut problem = problems(1).remove(0);
+        problem["userGrade"] = Value::from(7);
+
+        let decoded = decode::<Problem>(SchemaMode::Strict, &mut HashSet::new(), "url", problem);
+        assert!(matches!(decoded, Err(ApiError::Decode { .. })));
+    }
+
+    #[test]
+    fn lenient_mode_keeps_wrong_types_in_extra() {
+        let mut problem = problems(1).remove(0);
+        problem["userGrade"] = Value::from(7);
+
+        let mut reported = HashSet::new();
+        let problem: Problem = decode(SchemaMode::Lenient, &mut reported, "url", problem).unwrap();
+
+        assert_eq!(problem.user_grade, None);
+        assert_eq!(problem.extra["userGrade"], Value::from(7));
+        assert!(reported.contains("$.userGrade"));
+    }
+
+    #[test]
+    fn lenient_mode_drops_elements_that_dont_fit() {
+        let mut problems = problems(3);
+        problems[1]["grade"] = Value::from(7);
+
+        let mut reported = HashSet::new();
+        let problems: Vec<Problem> = decode(
+            SchemaMode::Lenient,
+            &mut reported,
+            "url",
+            Value::Array(problems),
+        )
+        .unwrap();
+
+        let ids: Vec<_> = problems.iter().map(|problem| problem.api_id).collect();
+        assert_eq!(ids, vec![1, 3]);
+        assert!(reported.contains("$[]"));
+    }
+
+    #[test]
+    fn lenient_mode_gets_past_unknown_grades() {
+        let mut problems = problems(2);
+        problems[0]["userGrade"] = Value::from("10Z");
+        problems[1]["grade"] = Value::from("10Z");
+
+        let mut reported = HashSet::new();
+        let problems: Vec<Problem> = decode(
+            SchemaMode::Lenient,
+            &mut reported,
+            "url",
+            Value::Array(problems),
+        )
+        .unwrap();
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].extra["userGrade"], Value::from("10Z"));
+        assert!(reported.contains("$[].userGrade"));
+        assert!(reported.contains("$[]"));
+    }
+
+    #[test]
+    fn lenient_pages_drop_only_the_element() {
+        let mut problems = problems(2);
+        problems[0]["grade"] = Value::from(7);
+        let page = serde_json::json!({
+            "AggregateResults": null, "Data": problems, "Errors": null, "Total": 2
+        });
+
+        let mut reported = HashSet::new();
+        let page: Paged<Problem> = decode(SchemaMode::Lenient, &mut reported, "url", page).unwrap();
+
+        assert_eq!(page.total, 2);
+        assert_eq!(page.data.len(), 1);
+        assert!(reported.contains("$.Data[]"));
+    }
+}
@@ -0,0 +1,323 @@
+use super::{
+    error::excerpt,
+    recording::fixtures_in,
+    schema::{drift_of, DeclaredFields, DriftReport},
+    transport::split_url,
+    Exchange, HoldSetup, Paged, Problems, RepeatOrComment, User,
+};
+
+use log::warn;
+use serde_json::Value;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
+    path::Path,
+};
+
+// decodes the response of an endpoint with the model we use for it and returns the name
+// of the endpoint with the drift, the token is left out, it is no model
+fn drift_of_response(
+    url: &str,
+    value: Value,
+) -> Option<(
+    &'static str,
+    serde_json::Result<(DriftReport, DeclaredFields)>,
+)> {
+    let (path, _) = split_url(url);
+
+    if path.contains("/problems/v2/") {
+        Some(("problems", drift_of::<Problems>(value)))
+    } else if path.contains("/Holdsetup") {
+        Some(("holdsetups", drift_of::<Vec<HoldSetup>>(value)))
+    } else if path.ends_with("/Users/Search") {
+        Some(("user search", drift_of::<Vec<User>>(value)))
+    } else if path.ends_with("/Problems/GetComments") {
+        Some(("comments", drift_of::<Paged<RepeatOrComment>>(value)))
+    } else if path.ends_with("/Problems/GetRepeats") {
+        Some(("repeats", drift_of::<Paged<RepeatOrComment>>(value)))
+    } else {
+        None
+    }
+}
+
+// the locations of all fields in `value`, and of the ones that are not null
+fn fields_of(
+    value: &Value,
+    path: &str,
+    sent: &mut BTreeSet<String>,
+    filled: &mut BTreeSet<String>,
+) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                let location = format!("{}.{}", path, key);
+
+                if !value.is_null() {
+                    filled.insert(location.clone());
+                }
+                fields_of(value, &location, sent, filled);
+                sent.insert(location);
+            }
+        }
+        Value::Array(values) => {
+            let path = format!("{}[]", path);
+
+            for value in values {
+                fields_of(value, &path, sent, filled);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[derive(Debug)]
+struct Finding {
+    count: usize,
+    example: String,
+}
+
+#[derive(Debug, Default)]
+struct EndpointReport {
+    responses: usize,
+    findings: BTreeMap<String, Finding>,
+    declared: DeclaredFields,
+    sent: BTreeSet<String>,
+    filled: BTreeSet<String>,
+}
+
+impl EndpointReport {
+    // the declared fields that never got a value, with whether they were sent at all
+    fn unfilled(&self) -> impl Iterator<Item = (&String, bool)> {
+        self.declared
+            .difference(&self.filled)
+            .map(move |location| (location, self.sent.contains(location)))
+    }
+}
+
+/// Compares responses of the api with our models.
+///
+/// The responses are decoded like in `SchemaMode::Lenient`, so this reports what a lenient
+/// download would warn about: fields we don't know, data in the fields we only ever got
+/// null for, values of the wrong type and elements that can't be decoded at all, like
+/// ones missing a field.
+///
+/// It also reports the other direction, the fields of our models that none of the
+/// responses had a value for, whether they were missing or always null. Optional fields
+/// are left empty by serde then, so they could be gone from the api or never be used.
+#[derive(Debug, Default)]
+pub struct SchemaReport {
+    responses: usize,
+    endpoints: BTreeMap<&'static str, EndpointReport>,
+}
+
+impl SchemaReport {
+    pub fn new() -> SchemaReport {
+        Default::default()
+    }
+
+    /// Adds the response of `exchange`, responses that are no model, like the token or error
+    /// statuses, are skipped.
+    pub fn add_exchange(&mut self, exchange: &Exchange) {
+        if !(200..300).contains(&exchange.response.status) {
+            return;
+        }
+
+        let url = &exchange.request.url;
+        let value: Value = match serde_json::from_str(&exchange.response.body) {
+            Ok(value) => value,
+            Err(_) => {
+                warn!("response of {} is no json, skipping it", url);
+                return;
+            }
+        };
+
+        let mut sent = BTreeSet::new();
+        let mut filled = BTreeSet::new();
+        fields_of(&value, "$", &mut sent, &mut filled);
+
+        let (name, drift, declared) = match drift_of_response(url, value) {
+            Some((name, Ok((drift, declared)))) => (name, drift, declared),
+            Some((name, Err(e))) => {
+                let mut drift = DriftReport::new();
+                drift.insert("$".to_owned(), e.to_string());
+                (name, drift, DeclaredFields::new())
+            }
+            None => return,
+        };
+
+        self.responses += 1;
+
+        let endpoint = self.endpoints.entry(name).or_default();
+        endpoint.responses += 1;
+        endpoint.declared.extend(declared);
+        endpoint.sent.extend(sent);
+        endpoint.filled.extend(filled);
+
+        for (location, example) in drift {
+            endpoint
+                .findings
+                .entry(location)
+                .or_insert(Finding {
+                    count: 0,
+                    example: excerpt(&example),
+                })
+                .count += 1;
+        }
+    }
+
+    /// Adds all exchanges in a fixtures directory written by a `RecordingTransport`.
+    pub fn add_fixtures<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<()> {
+        for path in fixtures_in(dir.as_ref())? {
+            let exchange = fs::read_to_string(&path)?;
+            let exchange: Exchange = serde_json::from_str(&exchange).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            })?;
+
+            self.add_exchange(&exchange);
+        }
+
+        Ok(())
+    }
+
+    /// Whether all responses matched our models, the declared fields without a value don't
+    /// count, placeholders never have one.
+    pub fn is_clean(&self) -> bool {
+        self.endpoints
+            .values()
+            .all(|endpoint| endpoint.findings.is_empty())
+    }
+}
+
+impl fmt::Display for SchemaReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "checked {} responses", self.responses)?;
+
+        for (name, endpoint) in &self.endpoints {
+            if endpoint.findings.is_empty() && endpoint.unfilled().next().is_none() {
+                writeln!(f, "{}: {} responses, matches", name, endpoint.responses)?;
+                continue;
+            }
+
+            writeln!(f, "{}: {} responses", name, endpoint.responses)?;
+
+            for (location, finding) in &endpoint.findings {
+                writeln!(
+                    f,
+                    "    {}: in {} responses, e.g. {}",
+                    location, finding.count, finding.example
+                )?;
+            }
+
+            for (location, sent) in endpoint.unfilled() {
+                if sent {
+                    writeln!(f, "    {}: declared, always null", location)?;
+                } else {
+                    writeln!(f, "    {}: declared, never sent", location)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moonboard_api::{Fixtures, HttpBody, HttpMethod, HttpRequest, HttpResponse};
+    use serde_json::json;
+
+    fn exchange(url: &str, body: Value) -> Exchange {
+        Exchange {
+            request: HttpRequest {
+                method: HttpMethod::Get,
+                url: url.to_owned(),
+                bearer: None,
+                body: HttpBody::Empty,
+            },
+            response: HttpResponse {
+                status: 200,
+                body: body.to_string(),
+            },
+        }
+    }
+
+    // only tells that the mock server still matches our models, see its README
+    #[test]
+    fn synthetic_session_matches() {
+        let mut report = SchemaReport::new();
+        report
+            .add_fixtures(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/fixtures/synthetic_session"
+            ))
+            .unwrap();
+
+        assert!(report.is_clean(), "{}", report);
+    }
+
+    #[test]
+    fn reports_drift_by_endpoint() {
+        let mut problems = Fixtures::generate(2).problems;
+        problems[0]["userGrade"] = json!(7);
+        problems[0]["new"] = json!("field");
+        problems[1].as_object_mut().unwrap().remove("name");
+
+        let mut report = SchemaReport::new();
+        report.add_exchange(&exchange(
+            "http://mock/v1/_moonapi/problems/v2/0",
+            json!({ "total": 2, "data": problems }),
+        ));
+
+        assert!(!report.is_clean());
+
+        let findings = &report.endpoints["problems"].findings;
+        let locations: Vec<_> = findings.keys().map(String::as_str).collect();
+        assert_eq!(
+            locations,
+            vec!["$.data[]", "$.data[].new", "$.data[].userGrade"]
+        );
+    }
+
+    #[test]
+    fn reports_declared_fields_without_values() {
+        let mut problems = Fixtures::generate(2).problems;
+        for problem in &mut problems {
+            problem.as_object_mut().unwrap().remove("userRating");
+        }
+        // one value is enough
+        problems[0]["userGrade"] = json!("6B+");
+
+        let mut report = SchemaReport::new();
+        report.add_exchange(&exchange(
+            "http://mock/v1/_moonapi/problems/v2/0",
+            json!({ "total": 2, "data": problems }),
+        ));
+
+        let unfilled: Vec<_> = report.endpoints["problems"]
+            .unfilled()
+            .map(|(location, sent)| (location.as_str(), sent))
+            .collect();
+        assert!(unfilled.contains(&("$.data[].userRating", false)));
+        assert!(unfilled.contains(&("$.data[].dateUpdated", true)));
+        assert!(unfilled.contains(&("$.data[].holdsetup.holdsets", true)));
+        assert!(!unfilled.contains(&("$.data[].userGrade", true)));
+        assert!(report.is_clean());
+
+        let report = report.to_string();
+        assert!(
+            report.contains("$.data[].userRating: declared, never sent"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("$.data[].dateUpdated: declared, always null"),
+            "{}",
+            report
+        );
+    }
+}
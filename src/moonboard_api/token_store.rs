@@ -0,0 +1,207 @@
+use super::Token;
+use log::warn;
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+/// Persists the login [`Token`] between runs, so we don't have to do a full
+/// password login every time a `MoonboardAPI` is created.
+pub trait TokenStore: Send + Sync {
+    /// Returns the stored token, `None` if there is none.
+    ///
+    /// A token that can't be parsed is treated like no token, failing to read the
+    /// store is an error.
+    fn load(&self) -> io::Result<Option<Token>>;
+    fn store(&self, token: &Token) -> io::Result<()>;
+    fn clear(&self) -> io::Result<()>;
+}
+
+/// Keeps the token only for the lifetime of the process, this is the default.
+#[derive(Default)]
+pub struct MemoryTokenStore {
+    token: Mutex<Option<Token>>,
+}
+
+impl MemoryTokenStore {
+    pub fn new() -> MemoryTokenStore {
+        Default::default()
+    }
+}
+
+impl TokenStore for MemoryTokenStore {
+    fn load(&self) -> io::Result<Option<Token>> {
+        Ok(self.token.lock().unwrap().clone())
+    }
+
+    fn store(&self, token: &Token) -> io::Result<()> {
+        *self.token.lock().unwrap() = Some(token.clone());
+        Ok(())
+    }
+
+    fn clear(&self) -> io::Result<()> {
+        *self.token.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+/// Stores the token as json in a file only readable by the current user.
+pub struct FileTokenStore {
+    path: PathBuf,
+}
+
+impl FileTokenStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> FileTokenStore {
+        FileTokenStore { path: path.into() }
+    }
+
+    fn tmp_path(&self) -> PathBuf {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        tmp.into()
+    }
+}
+
+#[cfg(unix)]
+fn open_private(path: &PathBuf) -> io::Result<fs::File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+
+    // mode is only applied when the file is created, so fix up preexisting files
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn open_private(path: &PathBuf) -> io::Result<fs::File> {
+    fs::File::create(path)
+}
+
+impl TokenStore for FileTokenStore {
+    fn load(&self) -> io::Result<Option<Token>> {
+        let content = match fs::read(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        match serde_json::from_slice(&content) {
+            Ok(token) => Ok(Some(token)),
+            Err(e) => {
+                // a corrupted cache only costs us a login, so don't fail because of it
+                warn!(
+                    "ignoring corrupted token cache {}: {}",
+                    self.path.display(),
+                    e
+                );
+                self.clear()?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn store(&self, token: &Token) -> io::Result<()> {
+        let tmp_path = self.tmp_path();
+
+        {
+            let mut file = open_private(&tmp_path)?;
+            file.write_all(&serde_json::to_vec(token)?)?;
+            file.sync_all()?;
+        }
+
+        // rename is atomic, so a crash can't leave a half written token behind
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    fn clear(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moonboard_api::tests::token;
+    use chrono::Utc;
+
+    // a fresh path in the temp dir, the file itself is not created
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "moonboard-token-{}-{}.json",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn stores_and_loads_token() {
+        let path = temp_path("roundtrip");
+        let store = FileTokenStore::new(&path);
+
+        assert!(store.load().unwrap().is_none());
+
+        store.store(&token("access", Utc::now())).unwrap();
+        let loaded = store.load().unwrap().unwrap();
+        assert_eq!(loaded.access_token, "access");
+
+        store.clear().unwrap();
+        assert!(store.load().unwrap().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn token_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_path("private");
+        // a preexisting file keeps its mode when overwritten, unless we fix it up
+        fs::write(&path, "{}").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let store = FileTokenStore::new(&path);
+        store.store(&token("access", Utc::now())).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        store.clear().unwrap();
+    }
+
+    #[test]
+    fn corrupted_token_is_cleared() {
+        let path = temp_path("corrupted");
+        fs::write(&path, "{\"access_token\": ").unwrap();
+
+        let store = FileTokenStore::new(&path);
+        assert!(store.load().unwrap().is_none());
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_token_is_an_error() {
+        // a directory can't be read as a file
+        let path = temp_path("unreadable");
+        fs::create_dir(&path).unwrap();
+
+        let store = FileTokenStore::new(&path);
+        assert!(store.load().is_err());
+
+        fs::remove_dir(&path).unwrap();
+    }
+}
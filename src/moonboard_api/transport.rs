@@ -0,0 +1,221 @@
+use futures_util::future::BoxFuture;
+use reqwest::{header::CONTENT_TYPE, Client, Method};
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+pub type TransportError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HttpMethod {
+    Get,
+    Post,
+}
+
+/// Request bodies are kept in serialized form, so a request can be sent again when retrying.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HttpBody {
+    Empty,
+    Json(String),
+    Form(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct HttpRequest {
+    pub method: HttpMethod,
+    pub url: String,
+    pub bearer: Option<String>,
+    pub body: HttpBody,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Splits `url` into its path and query, dropping the scheme and host.
+pub(super) fn split_url(url: &str) -> (&str, &str) {
+    let without_scheme = url.splitn(2, "://").last().unwrap_or(url);
+    let path = without_scheme
+        .find('/')
+        .map_or("/", |start| &without_scheme[start..]);
+
+    let mut parts = path.splitn(2, '?');
+    (parts.next().unwrap_or("/"), parts.next().unwrap_or(""))
+}
+
+/// Sends the raw http requests of a `MoonboardAPI`.
+///
+/// Only failures to get any response are errors, error status codes are handled by the caller.
+pub trait Transport: Send + Sync {
+    fn send<'a>(
+        &'a self,
+        request: &'a HttpRequest,
+    ) -> BoxFuture<'a, Result<HttpResponse, TransportError>>;
+}
+
+// lets callers keep a handle, for example to inspect the requests of a `FakeTransport`
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    fn send<'a>(
+        &'a self,
+        request: &'a HttpRequest,
+    ) -> BoxFuture<'a, Result<HttpResponse, TransportError>> {
+        (**self).send(request)
+    }
+}
+
+/// Talks to the real servers, this is the default.
+#[derive(Default)]
+pub struct ReqwestTransport {
+    client: Client,
+}
+
+impl ReqwestTransport {
+    pub fn new() -> ReqwestTransport {
+        Default::default()
+    }
+
+    pub fn with_client(client: Client) -> ReqwestTransport {
+        ReqwestTransport { client }
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn send<'a>(
+        &'a self,
+        request: &'a HttpRequest,
+    ) -> BoxFuture<'a, Result<HttpResponse, TransportError>> {
+        Box::pin(async move {
+            let method = match request.method {
+                HttpMethod::Get => Method::GET,
+                HttpMethod::Post => Method::POST,
+            };
+
+            let mut builder = self.client.request(method, &request.url);
+
+            if let Some(bearer) = &request.bearer {
+                builder = builder.bearer_auth(bearer);
+            }
+
+            builder = match &request.body {
+                HttpBody::Empty => builder,
+                HttpBody::Json(json) => builder
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(json.clone()),
+                HttpBody::Form(form) => builder
+                    .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(form.clone()),
+            };
+
+            let response = builder.send().await?;
+            let status = response.status().as_u16();
+            let body = response.text().await?;
+
+            Ok(HttpResponse { status, body })
+        })
+    }
+}
+
+/// Answers requests from canned responses, to run a `MoonboardAPI` without network.
+///
+/// Responses are matched by method, full url (including the query string) and optionally
+/// the request body, responses for a body take precedence over those for any body.
+/// Multiple responses for the same request are returned in order, the last one is repeated.
+/// Requests without a response get a 404.
+#[derive(Default)]
+pub struct FakeTransport {
+    // `None` matches any body
+    responses: Mutex<HashMap<(HttpMethod, String, Option<HttpBody>), VecDeque<HttpResponse>>>,
+    requests: Mutex<Vec<HttpRequest>>,
+}
+
+impl FakeTransport {
+    pub fn new() -> FakeTransport {
+        Default::default()
+    }
+
+    pub fn respond<B: Into<String>>(
+        &self,
+        method: HttpMethod,
+        url: &str,
+        status: u16,
+        body: B,
+    ) -> &FakeTransport {
+        self.add_response((method, url.to_owned(), None), status, body.into())
+    }
+
+    /// Like `respond`, but only for requests with `request_body`, for example to answer the
+    /// pages of a download that only differ in the posted page number.
+    pub fn respond_to_body<B: Into<String>>(
+        &self,
+        method: HttpMethod,
+        url: &str,
+        request_body: HttpBody,
+        status: u16,
+        body: B,
+    ) -> &FakeTransport {
+        self.add_response(
+            (method, url.to_owned(), Some(request_body)),
+            status,
+            body.into(),
+        )
+    }
+
+    fn add_response(
+        &self,
+        key: (HttpMethod, String, Option<HttpBody>),
+        status: u16,
+        body: String,
+    ) -> &FakeTransport {
+        self.responses
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .push_back(HttpResponse { status, body });
+
+        self
+    }
+
+    /// All requests sent so far, in order.
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Transport for FakeTransport {
+    fn send<'a>(
+        &'a self,
+        request: &'a HttpRequest,
+    ) -> BoxFuture<'a, Result<HttpResponse, TransportError>> {
+        self.requests.lock().unwrap().push(request.clone());
+
+        let mut responses = self.responses.lock().unwrap();
+        let mut key = (
+            request.method,
+            request.url.clone(),
+            Some(request.body.clone()),
+        );
+
+        if !responses.contains_key(&key) {
+            key.2 = None;
+        }
+
+        let response = match responses.get_mut(&key) {
+            Some(queue) if queue.len() > 1 => queue.pop_front(),
+            Some(queue) => queue.front().cloned(),
+            None => None,
+        };
+
+        let response = response.unwrap_or_else(|| HttpResponse {
+            status: 404,
+            body: format!("no fake response for {:?} {}", request.method, request.url),
+        });
+
+        Box::pin(async move { Ok(response) })
+    }
+}
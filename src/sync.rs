@@ -0,0 +1,504 @@
+use crate::{
+    migrations::{migrate, MigrationError},
+    moonboard_api::{
+        problems, ApiError, CancellationToken, DownloadOptions, MoonboardAPI, Problem,
+    },
+};
+
+use chrono::NaiveDateTime;
+use log::{info, warn};
+use sqlx::{sqlite::SqliteConnection, Connection, Executor};
+
+use std::fmt;
+
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+#[derive(Debug)]
+pub enum SyncError {
+    Api(ApiError),
+    Database(sqlx::Error),
+    Migration(MigrationError),
+    /// The stored watermarks could not be parsed.
+    Watermarks(String),
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyncError::Api(e) => write!(f, "downloading problems failed: {}", e),
+            SyncError::Database(e) => write!(f, "writing problems failed: {}", e),
+            SyncError::Migration(e) => write!(f, "{}", e),
+            SyncError::Watermarks(msg) => write!(f, "invalid sync watermarks: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SyncError::Api(e) => Some(e),
+            SyncError::Database(e) => Some(e),
+            SyncError::Migration(e) => Some(e),
+            SyncError::Watermarks(_) => None,
+        }
+    }
+}
+
+impl From<ApiError> for SyncError {
+    fn from(e: ApiError) -> SyncError {
+        SyncError::Api(e)
+    }
+}
+
+impl From<sqlx::Error> for SyncError {
+    fn from(e: sqlx::Error) -> SyncError {
+        SyncError::Database(e)
+    }
+}
+
+impl From<MigrationError> for SyncError {
+    fn from(e: MigrationError) -> SyncError {
+        SyncError::Migration(e)
+    }
+}
+
+/// The newest insert, update and deletion dates of the synced problems, the next sync asks
+/// for everything after them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watermarks {
+    pub date_inserted: NaiveDateTime,
+    pub date_updated: NaiveDateTime,
+    pub date_deleted: NaiveDateTime,
+}
+
+impl Watermarks {
+    // `None` if there are no problems
+    fn of(problems: &[Problem]) -> Option<Watermarks> {
+        let date_inserted = problems
+            .iter()
+            .map(|problem| problem.date_inserted.naive_utc())
+            .max()?;
+
+        Some(
+            Watermarks {
+                date_inserted,
+                date_updated: date_inserted,
+                date_deleted: date_inserted,
+            }
+            .advanced(problems),
+        )
+    }
+
+    fn advanced(mut self, problems: &[Problem]) -> Watermarks {
+        for problem in problems {
+            self.date_inserted = self.date_inserted.max(problem.date_inserted.naive_utc());
+
+            if let Some(date_updated) = problem.date_updated {
+                self.date_updated = self.date_updated.max(date_updated.naive_utc());
+            }
+
+            if let Some(date_deleted) = problem.date_deleted {
+                self.date_deleted = self.date_deleted.max(date_deleted.naive_utc());
+            }
+        }
+
+        self
+    }
+
+    /// The watermarks of the last sync, `None` if there was none.
+    pub async fn load(conn: &mut SqliteConnection) -> Result<Option<Watermarks>, SyncError> {
+        let row: Option<(String, String, String)> = sqlx::query_as(
+            "SELECT date_inserted, date_updated, date_deleted FROM sync_watermarks WHERE id = 0",
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        let parse = |s: &str| {
+            NaiveDateTime::parse_from_str(s, DATE_FORMAT)
+                .map_err(|e| SyncError::Watermarks(format!("{}: {}", s, e)))
+        };
+
+        match row {
+            Some((date_inserted, date_updated, date_deleted)) => Ok(Some(Watermarks {
+                date_inserted: parse(&date_inserted)?,
+                date_updated: parse(&date_updated)?,
+                date_deleted: parse(&date_deleted)?,
+            })),
+            None => Ok(None),
+        }
+    }
+}
+
+/// What a `sync_problems` did.
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    /// Whether everything was downloaded instead of only the changes.
+    pub full: bool,
+    /// Problems that were inserted or updated, including the deleted ones.
+    pub written: usize,
+    /// Problems that were marked as deleted.
+    pub deleted: usize,
+    /// The sync was cancelled and nothing was written.
+    pub cancelled: bool,
+}
+
+/// Brings the problems in the database up to date.
+///
+/// Only the changes since the stored watermarks are downloaded. A full download is done
+/// instead when the database has no problems or no watermarks. A database that fails its
+/// integrity check is emptied and gets a full download too, as everything in it is suspect.
+/// The changes are written in a single transaction together with the new watermarks, so the
+/// database never holds half a sync. Deleted problems are kept, with `date_deleted` set.
+///
+/// Once `cancel` is cancelled, the download stops at the next page and nothing is written,
+/// as a partial download does not tell which changes are missing.
+pub async fn sync_problems(
+    api: &MoonboardAPI,
+    conn: &mut SqliteConnection,
+    cancel: Option<&CancellationToken>,
+) -> Result<SyncReport, SyncError> {
+    if !is_intact(conn).await? {
+        reset(conn).await?;
+    }
+
+    migrate(conn).await?;
+
+    let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM problems")
+        .fetch_one(&mut *conn)
+        .await?;
+
+    let watermarks = if count > 0 {
+        match Watermarks::load(conn).await {
+            Ok(watermarks) => watermarks,
+            Err(e) => {
+                warn!("{}, doing a full sync", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let options = DownloadOptions {
+        cancel,
+        ..Default::default()
+    };
+
+    let mut report = SyncReport {
+        full: watermarks.is_none(),
+        ..Default::default()
+    };
+
+    let download = match watermarks {
+        Some(watermarks) => {
+            info!("syncing problems changed since {:?}", watermarks);
+
+            api.problem_updates_with(
+                options,
+                watermarks.date_inserted,
+                Some(watermarks.date_updated),
+                Some(watermarks.date_deleted),
+            )
+            .await
+        }
+        None => {
+            info!("doing a full problem sync");
+
+            api.all_problems_with(options).await
+        }
+    };
+
+    let download = download?;
+    if let Some(cursor) = download.cancelled_at {
+        info!(
+            "problem sync was cancelled at {}, not writing anything",
+            cursor
+        );
+        report.cancelled = true;
+
+        return Ok(report);
+    }
+    let changed = download.items;
+
+    let new_watermarks = match watermarks {
+        Some(watermarks) => Some(watermarks.advanced(&changed)),
+        None => Watermarks::of(&changed),
+    };
+
+    let mut tx = conn.begin().await?;
+
+    if report.full {
+        tx.execute("DELETE FROM moves; DELETE FROM holdsets_for_problems; DELETE FROM problems;")
+            .await?;
+    }
+
+    for problem in changed {
+        if problem.date_deleted.is_some() {
+            report.deleted += 1;
+        }
+
+        // an update replaces the moves and holdsets of the problem too
+        problems::insert!(problem, |query| {
+            query.execute(&mut tx).await?;
+        });
+
+        report.written += 1;
+    }
+
+    if let Some(watermarks) = new_watermarks {
+        sqlx::query(
+            "INSERT OR REPLACE INTO sync_watermarks (id, date_inserted, date_updated, date_deleted) \
+             VALUES (0, $1, $2, $3)",
+        )
+        .bind(watermarks.date_inserted.format(DATE_FORMAT).to_string())
+        .bind(watermarks.date_updated.format(DATE_FORMAT).to_string())
+        .bind(watermarks.date_deleted.format(DATE_FORMAT).to_string())
+        .execute(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    info!("problem sync done: {:?}", report);
+
+    Ok(report)
+}
+
+async fn is_intact(conn: &mut SqliteConnection) -> Result<bool, SyncError> {
+    let (result,): (String,) = sqlx::query_as("PRAGMA quick_check")
+        .fetch_one(&mut *conn)
+        .await?;
+
+    if result != "ok" {
+        warn!(
+            "database failed its integrity check, emptying it: {}",
+            result
+        );
+    }
+
+    Ok(result == "ok")
+}
+
+// dropping the tables would read their corrupt pages, so they are only removed from the
+// schema and the vacuum rebuilds the file without them, `migrate` then starts from scratch
+async fn reset(conn: &mut SqliteConnection) -> Result<(), SyncError> {
+    conn.execute(
+        "PRAGMA writable_schema = ON; \
+         DELETE FROM sqlite_master; \
+         PRAGMA writable_schema = OFF; \
+         VACUUM; \
+         PRAGMA user_version = 0;",
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moonboard_api::{Endpoints, Fixtures, HoldSetup, MockBackend};
+    use futures_util::TryStreamExt;
+    use std::time::Duration;
+
+    // migrated, so foreign keys are checked
+    async fn database() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        migrate(&mut conn).await.unwrap();
+        conn
+    }
+
+    // the problem with the id `id` of the generated fixtures
+    fn problem(id: usize) -> Problem {
+        let problem = Fixtures::generate(id).problems.pop().unwrap();
+        serde_json::from_value(problem).unwrap()
+    }
+
+    async fn insert(conn: &mut SqliteConnection, problem: &Problem) {
+        problems::insert!(problem, |query| {
+            query.execute(&mut *conn).await.unwrap();
+        });
+    }
+
+    async fn count(conn: &mut SqliteConnection, sql: &str) -> i64 {
+        let (count,): (i64,) = sqlx::query_as(sql).fetch_one(&mut *conn).await.unwrap();
+        count
+    }
+
+    fn api() -> MoonboardAPI {
+        MoonboardAPI::new("mock".to_owned(), "mock".to_owned())
+            .with_transport(MockBackend::new(Fixtures::generate(3)))
+            .with_endpoints(Endpoints {
+                api_url: "http://mock".to_owned(),
+                website_url: "http://mock".to_owned(),
+            })
+            .with_rate_limit(Duration::from_secs(0))
+    }
+
+    #[tokio::test]
+    async fn syncs_all_problems_into_an_empty_database() {
+        let mut conn = database().await;
+
+        let report = sync_problems(&api(), &mut conn, None).await.unwrap();
+
+        assert!(report.full);
+        assert!(!report.cancelled);
+        assert_eq!(report.written, 3);
+        assert_eq!(count(&mut conn, "SELECT COUNT(*) FROM problems").await, 3);
+        assert!(Watermarks::load(&mut conn).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn corrupt_databases_get_a_full_sync() {
+        let path =
+            std::env::temp_dir().join(format!("moonboard-corrupt-{}.db", std::process::id()));
+        // an empty file is an empty database
+        std::fs::File::create(&path).unwrap();
+        let url = format!("sqlite://{}", path.display());
+
+        let mut conn = SqliteConnection::connect(&url).await.unwrap();
+        sync_problems(&api(), &mut conn, None).await.unwrap();
+
+        let (page_size,): (i64,) = sqlx::query_as("PRAGMA page_size")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        let (root,): (i64,) =
+            sqlx::query_as("SELECT rootpage FROM sqlite_master WHERE name = 'moves'")
+                .fetch_one(&mut conn)
+                .await
+                .unwrap();
+        conn.close().await.unwrap();
+
+        // an invalid page type in the header of the first page of the moves
+        {
+            use std::io::{Seek, SeekFrom, Write};
+
+            let mut file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+            file.seek(SeekFrom::Start(((root - 1) * page_size) as u64))
+                .unwrap();
+            file.write_all(&[0xff; 4]).unwrap();
+        }
+
+        let mut conn = SqliteConnection::connect(&url).await.unwrap();
+        assert!(!is_intact(&mut conn).await.unwrap());
+
+        let report = sync_problems(&api(), &mut conn, None).await.unwrap();
+
+        assert!(report.full);
+        assert_eq!(report.written, 3);
+        assert!(is_intact(&mut conn).await.unwrap());
+        assert_eq!(count(&mut conn, "SELECT COUNT(*) FROM moves").await, 6);
+
+        conn.close().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancelled_sync_writes_nothing() {
+        let mut conn = database().await;
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let report = sync_problems(&api(), &mut conn, Some(&cancel))
+            .await
+            .unwrap();
+
+        assert!(report.cancelled);
+        assert_eq!(report.written, 0);
+        assert_eq!(count(&mut conn, "SELECT COUNT(*) FROM problems").await, 0);
+    }
+
+    #[tokio::test]
+    async fn updates_replace_the_embedded_rows() {
+        let mut conn = database().await;
+
+        let mut problem = problem(1);
+        insert(&mut conn, &problem).await;
+
+        problem.moves.truncate(1);
+        problem.name = "RENAMED".to_owned();
+        insert(&mut conn, &problem).await;
+
+        assert_eq!(count(&mut conn, "SELECT COUNT(*) FROM problems").await, 1);
+        assert_eq!(
+            count(
+                &mut conn,
+                "SELECT COUNT(*) FROM problems WHERE name = 'RENAMED'"
+            )
+            .await,
+            1
+        );
+        assert_eq!(count(&mut conn, "SELECT COUNT(*) FROM moves").await, 1);
+        assert_eq!(
+            count(&mut conn, "SELECT COUNT(*) FROM holdsets_for_problems").await,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn loaded_problems_have_what_is_stored() {
+        let mut conn = database().await;
+
+        let problem = problem(1);
+        insert(&mut conn, &problem).await;
+
+        let loaded = problems::load_by_id(&mut conn, problem.api_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(loaded.api_id, problem.api_id);
+        assert_eq!(loaded.name, problem.name);
+        assert_eq!(loaded.grade, problem.grade);
+        assert_eq!(loaded.user_grade, problem.user_grade);
+        assert_eq!(loaded.date_inserted, problem.date_inserted);
+        assert_eq!(loaded.date_updated, problem.date_updated);
+        assert_eq!(loaded.setby_id, problem.setby_id);
+        assert_eq!(loaded.holdsetup.api_id, problem.holdsetup.api_id);
+
+        // in the order they were inserted
+        let moves = |problem: &Problem| {
+            problem
+                .moves
+                .iter()
+                .map(|m| (m.description, m.is_start, m.is_end))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(moves(&loaded), moves(&problem));
+
+        let holdsets = |problem: &Problem| {
+            problem
+                .holdsets
+                .iter()
+                .map(|holdset| (holdset.api_id, holdset.description.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(holdsets(&loaded), holdsets(&problem));
+        assert!(loaded.holdsets[0].locations.is_none());
+
+        let setups: Vec<HoldSetup> =
+            vec![serde_json::from_value(Fixtures::generate(1).holdsetups[0].clone()).unwrap()];
+        assert_eq!(
+            loaded.resolve_holds(&setups).unwrap().moves,
+            problem.resolve_holds(&setups).unwrap().moves
+        );
+    }
+
+    #[tokio::test]
+    async fn loads_all_problems_with_their_own_moves() {
+        let mut conn = database().await;
+
+        let mut second = problem(2);
+        second.moves.truncate(1);
+        insert(&mut conn, &second).await;
+        insert(&mut conn, &problem(1)).await;
+
+        let loaded: Vec<Problem> = problems::load_all(&mut conn).try_collect().await.unwrap();
+
+        let ids: Vec<_> = loaded.iter().map(|problem| problem.api_id).collect();
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(loaded[0].moves.len(), 2);
+        assert_eq!(loaded[1].moves.len(), 1);
+    }
+}
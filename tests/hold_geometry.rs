@@ -0,0 +1,71 @@
+// Reads the hold geometry the way a tool drawing problems would, from outside the crate.
+
+use moonboard::{BoardGeometry, BoardHold, Hold, HoldLocation, HoldSet, HoldSetup};
+
+const HOLD_SETUP: &str = r##"{
+    "id": 1,
+    "isLocked": false,
+    "setby": null,
+    "apiId": 15,
+    "description": "MoonBoard Masters 2017",
+    "holdsets": [{
+        "id": 1,
+        "color": "#ffffff",
+        "apiId": 1,
+        "description": "Original School Holds",
+        "holds": [{
+            "holdType": 0,
+            "holdsetDescription": null,
+            "id": 1,
+            "location": {
+                "color": "#ff0000",
+                "description": "A5",
+                "direction": 0,
+                "directionString": "N",
+                "holdNumber": "1",
+                "id": 1,
+                "rotation": 90,
+                "type": 0,
+                "x": 0.0,
+                "y": 4.0,
+                "holdset": null
+            },
+            "number": "1"
+        }]
+    }],
+    "active": true,
+    "allowClimbMethods": true,
+    "dateDeleted": null,
+    "dateInserted": "2017-01-01T00:00:00",
+    "dateUpdated": "2017-01-01T00:00:00",
+    "holdLayoutId": 1,
+    "moonBoardConfigurations": [{
+        "description": "40° MoonBoard",
+        "highGrade": "8B+",
+        "lowGrade": "6A+",
+        "id": 1
+    }]
+}"##;
+
+#[test]
+fn hold_geometry_is_readable() {
+    let setup: HoldSetup = serde_json::from_str(HOLD_SETUP).unwrap();
+
+    let holdset: &HoldSet = &setup.holdsets[0];
+    let hold: &Hold = &holdset.holds[0];
+    let location: &HoldLocation = &hold.location;
+    assert_eq!(location.description, "A5");
+    assert_eq!((location.x, location.y), (0.0, 4.0));
+
+    let geometry: BoardGeometry = setup.geometry();
+    let board_hold: &BoardHold = &geometry.holds[0];
+    assert_eq!(geometry.hold_setup_api_id, Some(15));
+    assert_eq!(board_hold.coordinate, "A5".parse().unwrap());
+    assert_eq!(board_hold.holdset, "Original School Holds");
+    assert_eq!(board_hold.rotation, 90);
+    // the color of the location wins over the one of the holdset
+    assert_eq!(
+        (board_hold.color.r, board_hold.color.g, board_hold.color.b),
+        (255, 0, 0)
+    );
+}
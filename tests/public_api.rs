@@ -0,0 +1,47 @@
+// Configures the api and reads problems with the exported types only, from outside the crate.
+
+use moonboard::{
+    BoardSize, DownloadCheckpoint, DownloadLimits, DownloadOptions, DownloadProgress, Endpoints,
+    FileTokenStore, Grade, HoldCoordinate, MoonboardAPI, ProgressReporter, RetryPolicy, VGrade,
+};
+
+use std::time::Duration;
+
+struct Silent;
+
+impl ProgressReporter for Silent {
+    fn report(&self, _progress: &DownloadProgress) {}
+}
+
+#[test]
+fn api_is_configurable() {
+    let dir = std::env::temp_dir();
+
+    let _api = MoonboardAPI::new("user".to_owned(), "password".to_owned())
+        .with_token_store(FileTokenStore::new(
+            dir.join("moonboard-public-api-token.json"),
+        ))
+        .with_endpoints(Endpoints::default())
+        .with_retry_policy(RetryPolicy::default())
+        .with_download_limits(DownloadLimits {
+            max_pages: Some(10),
+            time_budget: Some(Duration::from_secs(60)),
+        });
+
+    let checkpoint = DownloadCheckpoint::new(dir.join("moonboard-public-api.jsonl"));
+    let options = DownloadOptions {
+        checkpoint: Some(&checkpoint),
+        progress: Some(&Silent),
+        ..Default::default()
+    };
+    assert!(options.cancel.is_none());
+}
+
+#[test]
+fn grades_and_coordinates_are_usable() {
+    let grade: Grade = "6B+".parse().unwrap();
+    assert_eq!(grade.to_v(), VGrade(4));
+
+    let coordinate: HoldCoordinate = "K18".parse().unwrap();
+    assert!(BoardSize::MOONBOARD.contains(coordinate));
+}